@@ -0,0 +1,71 @@
+use proc_macro2::TokenStream;
+use quote::{quote, quote_spanned};
+use syn::spanned::Spanned;
+
+use crate::hash::HashMap;
+
+/// Derive `TryConvert<T>` for every variant of an enum that wraps a single value of type `T`.
+pub fn try_convert(input: syn::DeriveInput) -> Result<TokenStream, TokenStream> {
+    let mut crate_name = quote! { ::parse_it };
+    for attr in &input.attrs {
+        if attr.path().is_ident("parse_it") {
+            attr.parse_nested_meta(|meta| {
+                if meta.path.is_ident("crate") {
+                    let value = meta.value()?;
+                    let value = value.parse::<syn::LitStr>()?;
+                    let path = value
+                        .parse::<syn::Path>()
+                        .map_err(|_| syn::Error::new_spanned(value, "expected a valid path"))?;
+                    crate_name = quote! { #path };
+                } else {
+                    Err(syn::Error::new_spanned(meta.path, "unknown attribute"))?
+                }
+                Ok(())
+            })
+            .map_err(|e| e.to_compile_error())?;
+        }
+    }
+
+    let syn::Data::Enum(data) = &input.data else {
+        return Err(quote_spanned! { input.ident.span() =>
+            compile_error!("`TryConvert` can only be derived for enums");
+        });
+    };
+
+    let name = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+
+    let mut seen = HashMap::default();
+    let mut impls = TokenStream::new();
+    for variant in &data.variants {
+        let syn::Fields::Unnamed(fields) = &variant.fields else {
+            continue;
+        };
+        if fields.unnamed.len() != 1 {
+            continue;
+        }
+        let ty = &fields.unnamed[0].ty;
+        let key = quote! { #ty }.to_string();
+        if let Some(prev) = seen.insert(key, variant.ident.clone()) {
+            let e = format!(
+                "variants `{prev}` and `{}` wrap the same type, the conversion is ambiguous",
+                variant.ident
+            );
+            return Err(quote_spanned! { ty.span() => compile_error!(#e); });
+        }
+
+        let variant = &variant.ident;
+        impls.extend(quote! {
+            impl #impl_generics #crate_name::lexer::TryConvert<#ty> for #name #ty_generics #where_clause {
+                fn try_convert(&self) -> ::std::option::Option<#ty> {
+                    match self {
+                        Self::#variant(value) => ::std::option::Option::Some(::std::clone::Clone::clone(value)),
+                        #[allow(unreachable_patterns)]
+                        _ => ::std::option::Option::None,
+                    }
+                }
+            }
+        });
+    }
+    Ok(impls)
+}
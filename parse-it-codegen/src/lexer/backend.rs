@@ -1,13 +1,22 @@
 use proc_macro2::{Span, TokenStream};
-use quote::{format_ident, quote};
-use syn::{punctuated::Punctuated, visit_mut::VisitMut};
+use quote::{format_ident, quote, quote_spanned};
+use syn::{punctuated::Punctuated, spanned::Spanned, visit::Visit, visit_mut::VisitMut};
 
-use crate::lexer::middle::{Action, LexerImpl, Middle};
+use crate::{
+    lexer::middle::{Action, LexerImpl, Middle},
+    utils::{compile_dfa, dfa_static},
+};
 
+#[derive(Clone)]
 pub struct Context {
     crate_name: TokenStream,
     lexbuf: syn::Ident,
-    _debug: bool,
+    longest_match: bool,
+    case_insensitive: bool,
+    debug: bool,
+    /// the lexer whose actions are expanded, and the modes it can switch to
+    /// via `push!`, if it produces tokens on its own
+    modes: Option<(syn::Ident, Vec<syn::Ident>)>,
 }
 
 impl Middle {
@@ -16,7 +25,10 @@ impl Middle {
         let ctx = Context {
             crate_name: self.crate_name,
             lexbuf: format_ident!("r#__lexbuf", span = Span::call_site()),
-            _debug: self.debug,
+            longest_match: self.longest_match,
+            case_insensitive: self.case_insensitive,
+            debug: self.debug,
+            modes: None,
         };
 
         for lexer in self.lexers {
@@ -41,30 +53,121 @@ impl LexerImpl {
     pub fn expand(self, ctx: &Context) -> Result<TokenStream, TokenStream> {
         let name = self.name;
         let vis = self.vis;
+        let mode = self.mode;
         let inputs = self.inputs;
         let ret_ty = if let Some(ref ret_ty) = self.ret_ty {
             quote! { #ret_ty }
         } else {
             quote! { () }
         };
+        let fields = input_fields(&inputs);
+        let ctx = &Context {
+            modes: fields.is_some().then(|| {
+                let modes = self.modes.iter().map(|(_, name)| name.clone());
+                (
+                    name.clone(),
+                    std::iter::once(name.clone()).chain(modes).collect(),
+                )
+            }),
+            ..ctx.clone()
+        };
 
         let mut regexes = vec![];
         let mut actions = vec![];
         for (i, rule) in self.rules.into_iter().enumerate() {
-            regexes.push(rule.pattern);
+            regexes.push(rule.pattern.clone());
+            let (action, ret_ty) = rule.actions.0.expand(ctx)?;
+            let action = if rule.keywords.is_empty() {
+                action
+            } else {
+                let (words, keywords): (Vec<_>, Vec<_>) = rule.keywords.into_iter().unzip();
+                let keywords = keywords
+                    .iter()
+                    .map(|keyword| keyword.expand(ctx).map(|(action, _)| action))
+                    .collect::<Result<Vec<_>, _>>()?;
+                if ctx.case_insensitive {
+                    let crate_name = &ctx.crate_name;
+                    quote! {
+                        match __self {
+                            #(__self if #crate_name::lexer::eq_ignore_case(__self, #words) => #keywords,)*
+                            _ => #action,
+                        }
+                    }
+                } else {
+                    quote! {
+                        match __self {
+                            #(#words => #keywords,)*
+                            _ => #action,
+                        }
+                    }
+                }
+            };
             let (action, _) = rule.actions.1.into_iter().try_fold(
-                rule.actions.0.expand(ctx)?,
+                (action, ret_ty),
                 |(inner, inner_ty), it| -> Result<_, TokenStream> {
                     let (action, ret_ty) = it.expand(ctx)?;
+                    let bindings = it.args.iter().map(|(input, arg)| {
+                        let pat = &input.pat;
+                        let ty = &input.ty;
+                        quote! {
+                            #[allow(unused_variables)]
+                            let #pat: #ty = #arg;
+                        }
+                    });
                     Ok((
                         quote! {{
-                            let __self: #inner_ty = #inner;
+                            let __self: #inner_ty = {
+                                #(#bindings)*
+                                #inner
+                            };
                             #action
                         }},
                         ret_ty,
                     ))
                 },
             )?;
+            let action = if let Some(close) = rule.close {
+                let crate_name = &ctx.crate_name;
+                let lexbuf = &ctx.lexbuf;
+                let open = &rule.pattern;
+                quote! {{
+                    static DELIMITERS: ::std::sync::LazyLock<#crate_name::lexer::Regex> =
+                        ::std::sync::LazyLock::new(|| {
+                            #crate_name::lexer::Regex::new_many(&[#close, #open]).unwrap()
+                        });
+                    #lexbuf.nested(&DELIMITERS)?;
+                    let __self = #lexbuf.lexeme()?;
+                    #action
+                }}
+            } else {
+                action
+            };
+            let action = if rule.captures.is_empty() {
+                action
+            } else {
+                let crate_name = &ctx.crate_name;
+                let lexbuf = &ctx.lexbuf;
+                let pattern = syn::LitStr::new(
+                    &format!(r"(?:{})\z", rule.pattern.value()),
+                    rule.pattern.span(),
+                );
+                let bindings = rule.captures.iter().map(|capture| {
+                    let name = &capture.name;
+                    let index = capture.index;
+                    if capture.optional {
+                        quote! { let #name: Option<&'lex str> = __captures[#index]; }
+                    } else {
+                        quote! { let #name: &'lex str = __captures[#index].unwrap(); }
+                    }
+                });
+                quote! {{
+                    static CAPTURES: ::std::sync::LazyLock<#crate_name::lexer::Regex> =
+                        ::std::sync::LazyLock::new(|| #crate_name::lexer::Regex::new(#pattern).unwrap());
+                    let __captures = #lexbuf.captures(&CAPTURES)?;
+                    #(#bindings)*
+                    #action
+                }}
+            };
             actions.push(quote! {
                 #i => #action
             });
@@ -73,17 +176,137 @@ impl LexerImpl {
         let crate_name = &ctx.crate_name;
         let lexbuf = &ctx.lexbuf;
 
-        let lexer_impl = if inputs.is_empty() {
+        let error = if let Some(action) = self.error {
+            let (action, _) = action.expand(ctx)?;
             quote! {
-                impl #crate_name::LexIt for #name {
-                    type Token<'lex> = #ret_ty;
+                if #lexbuf.advance_char().is_none() {
+                    return Err(#lexbuf.unexpected());
+                }
+                let __start = #lexbuf.span().start;
+                let __self = #lexbuf.lexeme()?;
+                let value = #action;
+                #lexbuf.extend_from(__start);
+                return Ok(Some(value));
+            }
+        } else {
+            quote! { return Err(#lexbuf.unexpected()); }
+        };
+
+        let literals = if self.literals.is_empty() {
+            quote! {}
+        } else {
+            let (literals, variants): (Vec<_>, Vec<_>) = self
+                .literals
+                .into_iter()
+                .map(|l| (l.literal, l.variant))
+                .unzip();
+            let kinds = 0..literals.len();
+            quote! {
+                const LITERALS: &'static [&'static str] = &[#(#literals),*];
+
+                fn kind(token: &Self::Token<'_>) -> Option<usize> {
+                    #[allow(unreachable_patterns)]
+                    match token {
+                        #(#variants { .. } => Some(#kinds),)*
+                        _ => None,
+                    }
+                }
+            }
+        };
 
-                    fn new() -> Self {
-                        Self
+        let fallback = if ctx.longest_match {
+            // ties between matches of the same length go to the earliest rule
+            quote! {
+                #crate_name::lexer::Regex::builder()
+                    .configure(
+                        #crate_name::lexer::Regex::config()
+                            .match_kind(#crate_name::lexer::MatchKind::All)
+                    )
+                    .build_many(&[#(#regexes),*])
+                    .unwrap()
+            }
+        } else {
+            quote! {
+                #crate_name::lexer::Regex::new_many(&[#(#regexes),*]).unwrap()
+            }
+        };
+
+        let patterns = regexes.iter().map(|lit| lit.value()).collect::<Vec<_>>();
+        let (regex, run) = if let Some(dfa) = compile_dfa(&patterns, ctx.longest_match) {
+            let regex = dfa_static(crate_name, dfa);
+            (regex, quote! { #lexbuf.run_dfa(&*DFA) })
+        } else {
+            let regex = quote! {
+                static REGEX: ::std::sync::LazyLock<#crate_name::lexer::Regex> =
+                    ::std::sync::LazyLock::new(|| #fallback);
+            };
+            (regex, quote! { #lexbuf.run(&REGEX) })
+        };
+
+        let (mode_ids, mode_names): (Vec<_>, Vec<_>) = self.modes.into_iter().unzip();
+        // the bounds on the inputs are higher-ranked, so that they are checked
+        // where the impls are used, and lexers with inputs that are not `Clone`
+        // or `Default` only lack those impls
+        let lexer_struct = match &fields {
+            Some(fields) if !fields.is_empty() => {
+                let (names, tys): (Vec<_>, Vec<_>) = fields.iter().cloned().unzip();
+                quote! {
+                    #vis struct #name {
+                        #(pub #names: #tys,)*
                     }
 
-                    fn next<'lex>(&self, #lexbuf: &mut #crate_name::LexerState<'lex>) -> Option<Self::Token<'lex>> {
-                        Self::run(#lexbuf).ok().flatten()
+                    impl ::std::clone::Clone for #name
+                    where
+                        #(for<'lex> #tys: ::std::clone::Clone,)*
+                    {
+                        fn clone(&self) -> Self {
+                            Self { #(#names: ::std::clone::Clone::clone(&self.#names),)* }
+                        }
+                    }
+
+                    impl ::std::default::Default for #name
+                    where
+                        #(for<'lex> #tys: ::std::default::Default,)*
+                    {
+                        fn default() -> Self {
+                            Self { #(#names: ::std::default::Default::default(),)* }
+                        }
+                    }
+                }
+            }
+            _ => quote! {
+                #[derive(Clone, Copy, Debug, Default)]
+                #vis struct #name;
+            },
+        };
+        let debug_print = if ctx.debug {
+            let name_str = name.to_string();
+            quote! {
+                let span = #lexbuf.span();
+                eprintln!("{}: {:?} at {}..{}", #name_str, result, span.start, span.end);
+            }
+        } else {
+            quote! {}
+        };
+        let lexer_impl = if let Some(fields) = fields {
+            let (names, tys): (Vec<_>, Vec<_>) = fields.into_iter().unzip();
+            quote! {
+                impl #crate_name::LexIt for #name
+                where
+                    #(for<'lex> #tys: ::std::clone::Clone,)*
+                {
+                    type Token<'lex> = #ret_ty;
+
+                    #literals
+
+                    fn next<'lex>(&self, #lexbuf: &mut #crate_name::LexerState<'lex>) -> Result<Option<Self::Token<'lex>>, #crate_name::lexer::LexError> {
+                        let result = #crate_name::lexer::end_of_stream(match #lexbuf.mode() {
+                            None | Some(Self::MODE) => Self::run(#lexbuf, #(self.#names.clone()),*),
+                            #(Some(#mode_ids) => #mode_names::run(#lexbuf),)*
+                            Some(mode) => unreachable!("lexer mode {mode} cannot produce tokens for this lexer"),
+                        });
+                        #debug_print
+                        result
                     }
                 }
             }
@@ -92,15 +315,10 @@ impl LexerImpl {
         };
 
         Ok(quote! {
-            #[derive(Clone, Copy, Debug)]
-            #vis struct #name;
+            #lexer_struct
 
             impl #name {
-                thread_local! {
-                    static REGEX: #crate_name::lexer::Regex = #crate_name::lexer::Regex::new_many(
-                        &[#(#regexes),*]
-                    ).unwrap();
-                }
+                pub const MODE: usize = #mode;
 
                 #[allow(
                     dead_code,
@@ -114,22 +332,24 @@ impl LexerImpl {
                 pub fn run<'lex>(
                     #lexbuf: &mut #crate_name::lexer::LexerState<'lex>,
                     #(#inputs),*
-                ) -> Result<Option<#ret_ty>, ()> {
-                    Self::REGEX.with(|regex| {
-                        'lex: loop {
-                            if let Some(pat) = #lexbuf.run(regex) {
-                                let __self = #lexbuf.lexeme();
-                                let value = match pat.as_u32() as usize {
-                                    #(#actions,)*
-                                    _ => unreachable!(),
-                                };
-                                return Ok(Some(value));
-                            } else {
-                                return Err(());
-                            }
+                ) -> Result<Option<#ret_ty>, #crate_name::lexer::LexError> {
+                    #regex
+
+                    'lex: loop {
+                        if let Some(pat) = #run {
+                            let __start = #lexbuf.span().start;
+                            let __self = #lexbuf.lexeme()?;
+                            let value = match pat.as_u32() as usize {
+                                #(#actions,)*
+                                _ => unreachable!(),
+                            };
+                            #lexbuf.extend_from(__start);
+                            return Ok(Some(value));
+                        } else {
+                            #error
                         }
-                        Ok(None)
-                    })
+                    }
+                    Ok(None)
                 }
             }
 
@@ -138,17 +358,48 @@ impl LexerImpl {
     }
 }
 
+/// The fields of a lexer holding its inputs, if they are all named and owned,
+/// so that the lexer can produce tokens on its own.
+fn input_fields(inputs: &[syn::PatType]) -> Option<Vec<(syn::Ident, syn::Type)>> {
+    struct BorrowVisitor(bool);
+    impl<'ast> Visit<'ast> for BorrowVisitor {
+        fn visit_lifetime(&mut self, _: &'ast syn::Lifetime) {
+            self.0 = true;
+        }
+        fn visit_type_reference(&mut self, _: &'ast syn::TypeReference) {
+            self.0 = true;
+        }
+    }
+
+    inputs
+        .iter()
+        .map(|input| {
+            let syn::Pat::Ident(pat) = &*input.pat else {
+                return None;
+            };
+            if pat.by_ref.is_some() || pat.subpat.is_some() {
+                return None;
+            }
+            let mut visitor = BorrowVisitor(false);
+            visitor.visit_type(&input.ty);
+            (!visitor.0).then(|| (pat.ident.clone(), (*input.ty).clone()))
+        })
+        .collect()
+}
+
 struct ExpandLexMacroVisitor {
     crate_name: TokenStream,
     lexbuf: syn::Ident,
+    modes: Option<(syn::Ident, Vec<syn::Ident>)>,
     failure: Vec<TokenStream>,
 }
 
 impl ExpandLexMacroVisitor {
-    pub fn new(crate_name: TokenStream, lexbuf: syn::Ident) -> Self {
+    pub fn new(ctx: &Context) -> Self {
         Self {
-            crate_name,
-            lexbuf,
+            crate_name: ctx.crate_name.clone(),
+            lexbuf: ctx.lexbuf.clone(),
+            modes: ctx.modes.clone(),
             failure: vec![],
         }
     }
@@ -191,19 +442,81 @@ impl VisitMut for ExpandLexMacroVisitor {
                 Ok(lex_macro) => {
                     let LexMacro { lexer, args } = lex_macro;
                     i.path = syn::parse_quote!(#crate_name::identity);
-                    i.tokens = quote! { #lexer::run(#lexbuf, #(#args),*)? };
+                    i.tokens = quote! {
+                        #lexer::run(#lexbuf, #(#args),*)
+                            .map_err(#crate_name::lexer::LexError::unterminated)?
+                    };
                 }
                 Err(e) => self.failure.push(e.to_compile_error()),
             }
+        } else if i.path.is_ident("push") {
+            let crate_name = &self.crate_name;
+            let lexbuf = &self.lexbuf;
+            match syn::parse2::<syn::Ident>(i.tokens.clone()) {
+                Ok(mode) => match &self.modes {
+                    Some((lexer, modes)) if !modes.contains(&mode) => {
+                        let e = format!(
+                            "Lexer `{mode}` is not a mode of `{lexer}`: modes are the lexers \
+                             without inputs that produce the same tokens"
+                        );
+                        self.failure
+                            .push(quote_spanned! { mode.span() => compile_error!(#e); });
+                    }
+                    _ => {
+                        i.path = syn::parse_quote!(#crate_name::identity);
+                        i.tokens = quote! { #lexbuf.push_mode(#mode::MODE) };
+                    }
+                },
+                Err(e) => self.failure.push(e.to_compile_error()),
+            }
+        } else if i.path.is_ident("layout") {
+            let crate_name = &self.crate_name;
+            let lexbuf = &self.lexbuf;
+            match syn::parse2::<syn::Expr>(i.tokens.clone()) {
+                Ok(syn::Expr::Lit(syn::ExprLit {
+                    lit: syn::Lit::Int(tab_width),
+                    ..
+                })) if tab_width.base10_parse::<usize>().ok() == Some(0) => {
+                    let e = "The tab width of `layout!` must be positive";
+                    self.failure
+                        .push(quote_spanned! { tab_width.span() => compile_error!(#e); });
+                }
+                Ok(tab_width) => {
+                    i.path = syn::parse_quote!(#crate_name::identity);
+                    i.tokens = quote! { #lexbuf.layout(#tab_width)? };
+                }
+                Err(e) => self.failure.push(e.to_compile_error()),
+            }
+        } else if i.path.is_ident("pop") {
+            let crate_name = &self.crate_name;
+            let lexbuf = &self.lexbuf;
+            if i.tokens.is_empty() {
+                i.path = syn::parse_quote!(#crate_name::identity);
+                i.tokens = quote! { #lexbuf.pop_mode() };
+            } else {
+                let e = "`pop!` takes no arguments";
+                self.failure
+                    .push(quote_spanned! { i.tokens.span() => compile_error!(#e); });
+            }
         }
     }
 }
 
 impl Action {
     pub fn expand(&self, ctx: &Context) -> Result<(TokenStream, TokenStream), TokenStream> {
-        let mut action = self.action.clone();
+        if self.trivia {
+            let lexbuf = &ctx.lexbuf;
+            return Ok((
+                quote! {{
+                    #lexbuf.add_trivia();
+                    continue;
+                }},
+                self.ret_ty(),
+            ));
+        }
 
-        let mut visitor = ExpandLexMacroVisitor::new(ctx.crate_name.clone(), ctx.lexbuf.clone());
+        let mut action = self.action.clone();
+        let mut visitor = ExpandLexMacroVisitor::new(ctx);
         visitor.visit_expr_mut(&mut action);
         if let Some(failure) = visitor.failure() {
             return Err(failure);
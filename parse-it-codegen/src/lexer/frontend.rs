@@ -1,12 +1,13 @@
 use std::rc::Rc;
 
-use proc_macro2::TokenStream;
+use proc_macro2::{Span, TokenStream};
 use quote::{quote, quote_spanned};
-use syn::visit_mut::VisitMut;
+use regex_syntax::hir::{Hir, HirKind};
+use syn::{spanned::Spanned, visit_mut::VisitMut};
 
 use crate::{
     hash::HashMap,
-    lexer::middle::{Action, LexerImpl, Middle, Rule},
+    lexer::middle::{Action, CaptureGroup, LexerImpl, Middle, Rule, TokenLiteral},
     syntax::{Lexer, LexerMod, LexerPattern, LexerRule},
     utils::RewriteSelfVisitor,
 };
@@ -14,12 +15,18 @@ use crate::{
 #[derive(Default)]
 struct Context {
     pub parse_macros: Rc<Vec<syn::Path>>,
+    pub token_literals: HashMap<syn::Ident, Vec<TokenLiteral>>,
+    pub unicode: bool,
+    pub case_insensitive: bool,
 }
 
 impl LexerMod {
-    pub fn compile(self) -> Result<Middle, TokenStream> {
+    pub fn compile(mut self) -> Result<Middle, TokenStream> {
         let ctx = Context {
             parse_macros: self.config.parse_macros.clone(),
+            token_literals: self.collect_token_literals()?,
+            unicode: self.config.unicode,
+            case_insensitive: self.config.case_insensitive,
         };
         let crate_name = match &self.config.crate_name {
             Some(crate_name) => quote! { #crate_name },
@@ -31,22 +38,95 @@ impl LexerMod {
             .iter()
             .map(|lexer| (lexer.name.clone(), lexer))
             .collect::<HashMap<_, _>>();
-        let lexers = self
+        let mut lexers = self
             .lexers
             .iter()
-            .map(|lexer| lexer.compile(&lexers, &ctx))
+            .enumerate()
+            .map(|(mode, lexer)| lexer.compile(&lexers, mode, &ctx))
             .collect::<Result<Vec<_>, _>>()?;
 
+        // a lexer can switch to any mode that produces the same tokens
+        let ret_ty = |lexer: &LexerImpl| lexer.ret_ty.as_ref().map(|ty| quote! { #ty }.to_string());
+        for i in 0..lexers.len() {
+            if !lexers[i].inputs.is_empty() {
+                continue;
+            }
+            lexers[i].modes = lexers
+                .iter()
+                .filter(|other| {
+                    other.mode != lexers[i].mode
+                        && other.inputs.is_empty()
+                        && ret_ty(other) == ret_ty(&lexers[i])
+                })
+                .map(|other| (other.mode, other.name.clone()))
+                .collect();
+        }
+
         let middle = Middle {
+            case_insensitive: self.config.case_insensitive,
             attrs: self.attrs,
             crate_name,
             mod_name: self.mod_name,
             items: self.items,
             lexers,
             debug: self.config.debug,
+            longest_match: self.config.longest_match,
         };
         Ok(middle)
     }
+
+    /// Collect `#[token("...")]` attributes on the variants of token enums,
+    /// removing them from the items.
+    fn collect_token_literals(
+        &mut self,
+    ) -> Result<HashMap<syn::Ident, Vec<TokenLiteral>>, TokenStream> {
+        let mut token_literals = HashMap::default();
+        for item in &mut self.items {
+            let syn::Item::Enum(item) = item else {
+                continue;
+            };
+            let mut literals: Vec<TokenLiteral> = vec![];
+            for variant in &mut item.variants {
+                let mut attrs = vec![];
+                for attr in std::mem::take(&mut variant.attrs) {
+                    if !attr.path().is_ident("token") {
+                        attrs.push(attr);
+                        continue;
+                    }
+                    let literal = match attr.parse_args::<syn::Lit>() {
+                        Ok(syn::Lit::Str(lit)) => lit.value(),
+                        Ok(syn::Lit::Char(lit)) => lit.value().to_string(),
+                        _ => {
+                            let e = "expected a string or character literal";
+                            return Err(quote_spanned! { attr.span() => compile_error!(#e); });
+                        }
+                    };
+                    if literals.iter().any(|l| l.variant.is_ident(&variant.ident)) {
+                        let e = format!("Variant `{}` already has a literal", variant.ident);
+                        return Err(quote_spanned! { attr.span() => compile_error!(#e); });
+                    }
+                    if literals.iter().any(|l| l.literal == literal) {
+                        let e = format!("Literal `{literal}` is already defined");
+                        return Err(quote_spanned! { attr.span() => compile_error!(#e); });
+                    }
+                    literals.push(TokenLiteral {
+                        literal,
+                        variant: variant.ident.clone().into(),
+                    });
+                }
+                variant.attrs = attrs;
+            }
+            if !literals.is_empty() {
+                let name = &item.ident;
+                for literal in &mut literals {
+                    let variant = &literal.variant;
+                    literal.variant = syn::parse_quote! { #name::#variant };
+                }
+                token_literals.insert(name.clone(), literals);
+            }
+        }
+        Ok(token_literals)
+    }
 }
 
 impl Lexer {
@@ -61,16 +141,30 @@ impl Lexer {
         for rule in &self.rules {
             match &rule.pattern {
                 LexerPattern::Regex(lit_str) => {
-                    if let Err(e) = regex_syntax::parse(&lit_str.value()) {
-                        let e = format!("Invalid regex pattern: {e}");
-                        return Err(quote_spanned! { lit_str.span() => compile_error!(#e); });
-                    }
+                    let pattern = compile_regex(lit_str, false, ctx)?;
                     rules.push(Rule {
-                        pattern: lit_str.clone(),
+                        captures: named_captures(&pattern)?,
+                        pattern,
+                        close: None,
+                        keywords: rule.compile_keywords(self.ty.clone(), ctx)?,
                         actions: (rule.compile(self.ty.clone(), ctx), vec![]),
                     });
                 }
-                LexerPattern::Name(ident) => {
+                LexerPattern::Nested(open, close) => {
+                    let pattern = compile_regex(open, true, ctx)?;
+                    rules.push(Rule {
+                        captures: named_captures(&pattern)?,
+                        pattern,
+                        close: Some(compile_regex(close, true, ctx)?),
+                        keywords: rule.compile_keywords(self.ty.clone(), ctx)?,
+                        actions: (rule.compile(self.ty.clone(), ctx), vec![]),
+                    });
+                }
+                LexerPattern::Name(ident, args) => {
+                    if let Some(keyword) = rule.keywords.first() {
+                        let e = "Keywords are not supported on included lexers";
+                        return Err(quote_spanned! { keyword.word.span() => compile_error!(#e); });
+                    }
                     if stack.contains(ident) {
                         let e = format!("Recursive inclusion of lexer `{ident}`");
                         return Err(quote_spanned! { ident.span() => compile_error!(#e); });
@@ -79,11 +173,27 @@ impl Lexer {
                         let e = format!("Lexer `{ident}` not found");
                         quote_spanned! { ident.span() => compile_error!(#e); }
                     })?;
-                    if !lexer.inputs.is_empty() {
-                        let e = format!("Cannot include lexer `{ident}` in another lexer, it has inputs defined");
+                    if lexer.inputs.len() != args.len() {
+                        let e = format!(
+                            "Lexer `{ident}` takes {} arguments, but {} were given",
+                            lexer.inputs.len(),
+                            args.len()
+                        );
                         return Err(quote_spanned! { ident.span() => compile_error!(#e); });
                     }
-                    let action = rule.compile(self.ty.clone(), ctx);
+                    let mut action = rule.compile(self.ty.clone(), ctx);
+                    action.args = lexer
+                        .inputs
+                        .iter()
+                        .zip(args)
+                        .map(|(input, arg)| {
+                            let mut arg = arg.clone();
+                            RewriteSelfVisitor::new(ctx.parse_macros.clone())
+                                .visit_expr_mut(&mut arg);
+                            (input.clone(), arg)
+                        })
+                        .collect();
+                    // error rules of the included lexer are not inherited
                     rules.extend(lexer.full_rules(lexers, stack, ctx)?.into_iter().map(
                         |mut rule| {
                             rule.actions.1.push(action.clone());
@@ -91,6 +201,7 @@ impl Lexer {
                         },
                     ));
                 }
+                LexerPattern::Error(_) => {}
             }
         }
         stack.pop();
@@ -100,6 +211,7 @@ impl Lexer {
     fn compile(
         &self,
         lexers: &HashMap<syn::Ident, &Lexer>,
+        mode: usize,
         ctx: &Context,
     ) -> Result<LexerImpl, TokenStream> {
         if self.rules.is_empty() {
@@ -107,29 +219,171 @@ impl Lexer {
             return Err(quote_spanned! { self.name.span() => compile_error!(#e); });
         }
         let rules = self.full_rules(lexers, &mut vec![], ctx)?;
+        let mut error = None;
+        for rule in &self.rules {
+            if let LexerPattern::Error(underscore) = &rule.pattern {
+                if error.is_some() {
+                    let e = format!("Lexer `{}` has more than one error rule", self.name);
+                    return Err(quote_spanned! { underscore.span() => compile_error!(#e); });
+                }
+                if let Some(keyword) = rule.keywords.first() {
+                    let e = "Keywords are not supported on error rules";
+                    return Err(quote_spanned! { keyword.word.span() => compile_error!(#e); });
+                }
+                error = Some(rule.compile(self.ty.clone(), ctx));
+            }
+        }
         let inputs = self.inputs.iter().cloned().collect();
+        let literals = match &self.ty {
+            Some(syn::Type::Path(ty)) if ty.qself.is_none() => ty
+                .path
+                .segments
+                .last()
+                .and_then(|seg| ctx.token_literals.get(&seg.ident))
+                .cloned()
+                .unwrap_or_default(),
+            _ => vec![],
+        };
         Ok(LexerImpl {
             name: self.name.clone(),
             rules,
             vis: self.vis.clone(),
             inputs,
             ret_ty: self.ty.clone(),
+            literals,
+            error,
+            mode,
+            modes: vec![],
         })
     }
 }
 
+/// Compile a regex literal of a lexer rule, applying its suffix and the flags
+/// of the lexer module.
+///
+/// Delimiters of nested rules must not match the empty string, or the nesting
+/// would never end.
+fn compile_regex(
+    lit_str: &syn::LitStr,
+    delimiter: bool,
+    ctx: &Context,
+) -> Result<syn::LitStr, TokenStream> {
+    let mut flags = String::new();
+    match lit_str.suffix() {
+        "" if !ctx.case_insensitive => {}
+        "" | "i" => flags.push('i'),
+        suffix => {
+            let e = format!("Unsupported regex suffix `{suffix}`");
+            return Err(quote_spanned! { lit_str.span() => compile_error!(#e); });
+        }
+    }
+    if !ctx.unicode {
+        flags.push_str("-u");
+    }
+    let pattern = if flags.is_empty() {
+        lit_str.value()
+    } else {
+        format!("(?{flags}:{})", lit_str.value())
+    };
+    let hir = regex_syntax::parse(&pattern).map_err(|e| {
+        let e = format!("Invalid regex pattern: {e}");
+        quote_spanned! { lit_str.span() => compile_error!(#e); }
+    })?;
+    if delimiter && hir.properties().minimum_len().is_none_or(|len| len == 0) {
+        let e = "Delimiters must not match the empty string";
+        return Err(quote_spanned! { lit_str.span() => compile_error!(#e); });
+    }
+    Ok(syn::LitStr::new(&pattern, lit_str.span()))
+}
+
+/// Collect the named capture groups of a compiled regex.
+fn named_captures(pattern: &syn::LitStr) -> Result<Vec<CaptureGroup>, TokenStream> {
+    fn collect(
+        hir: &Hir,
+        optional: bool,
+        pattern: &syn::LitStr,
+        captures: &mut Vec<CaptureGroup>,
+    ) -> Result<(), TokenStream> {
+        match hir.kind() {
+            HirKind::Capture(capture) => {
+                if let Some(name) = &capture.name {
+                    let name = syn::parse_str::<syn::Ident>(name).map_err(|_| {
+                        let e = format!("Capture group name `{name}` is not a valid identifier");
+                        quote_spanned! { pattern.span() => compile_error!(#e); }
+                    })?;
+                    captures.push(CaptureGroup {
+                        name: syn::Ident::new(&name.to_string(), Span::call_site()),
+                        index: capture.index as usize,
+                        optional,
+                    });
+                }
+                collect(&capture.sub, optional, pattern, captures)
+            }
+            HirKind::Repetition(repetition) => collect(
+                &repetition.sub,
+                optional || repetition.min == 0,
+                pattern,
+                captures,
+            ),
+            HirKind::Alternation(alternatives) => alternatives
+                .iter()
+                .try_for_each(|hir| collect(hir, true, pattern, captures)),
+            HirKind::Concat(hirs) => hirs
+                .iter()
+                .try_for_each(|hir| collect(hir, optional, pattern, captures)),
+            _ => Ok(()),
+        }
+    }
+
+    // the pattern has been validated by `compile_regex`
+    let hir = regex_syntax::parse(&pattern.value()).unwrap();
+    let mut captures = vec![];
+    collect(&hir, false, pattern, &mut captures)?;
+    Ok(captures)
+}
+
 impl LexerRule {
     fn compile(&self, ret_ty: Option<syn::Type>, ctx: &Context) -> Action {
-        let mut action = self.action.clone();
-
-        let mut visitor = RewriteSelfVisitor::new(ctx.parse_macros.clone());
-        visitor.visit_expr_mut(&mut action);
-        let self_ident = visitor.self_ident;
+        let mut action = compile_action(&self.action, ret_ty, ctx);
+        action.trivia = self.trivia;
+        action
+    }
 
-        Action {
-            action,
-            ret_ty,
-            self_ident,
+    fn compile_keywords(
+        &self,
+        ret_ty: Option<syn::Type>,
+        ctx: &Context,
+    ) -> Result<Vec<(syn::LitStr, Action)>, TokenStream> {
+        let mut keywords: Vec<(syn::LitStr, Action)> = vec![];
+        for keyword in &self.keywords {
+            let word = &keyword.word;
+            if !word.suffix().is_empty() {
+                let e = format!("Unsupported keyword suffix `{}`", word.suffix());
+                return Err(quote_spanned! { word.span() => compile_error!(#e); });
+            }
+            if keywords.iter().any(|(w, _)| w.value() == word.value()) {
+                let e = format!("Keyword `{}` is already defined", word.value());
+                return Err(quote_spanned! { word.span() => compile_error!(#e); });
+            }
+            let action = compile_action(&keyword.action, ret_ty.clone(), ctx);
+            keywords.push((word.clone(), action));
         }
+        Ok(keywords)
+    }
+}
+
+fn compile_action(action: &syn::Expr, ret_ty: Option<syn::Type>, ctx: &Context) -> Action {
+    let mut action = action.clone();
+
+    let mut visitor = RewriteSelfVisitor::new(ctx.parse_macros.clone());
+    visitor.visit_expr_mut(&mut action);
+    let self_ident = visitor.self_ident;
+
+    Action {
+        action,
+        trivia: false,
+        ret_ty,
+        self_ident,
+        args: vec![],
     }
 }
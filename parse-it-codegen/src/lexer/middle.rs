@@ -3,17 +3,44 @@ use proc_macro2::TokenStream;
 #[derive(Debug, Clone)]
 pub struct Action {
     pub action: syn::Expr,
+    /// record the lexeme as trivia and skip it, instead of the action
+    pub trivia: bool,
     pub ret_ty: Option<syn::Type>,
     /// replace `self` with this ident
     pub self_ident: syn::Ident,
+    /// arguments for the inputs of an included lexer, bound around the
+    /// actions of its rules
+    pub args: Vec<(syn::PatType, syn::Expr)>,
 }
 
 #[derive(Debug, Clone)]
 pub struct Rule {
     pub pattern: syn::LitStr,
+    /// the closing delimiter, if `pattern` opens a nested rule
+    pub close: Option<syn::LitStr>,
+    /// reserved words, replacing the first action when the lexeme is one
+    pub keywords: Vec<(syn::LitStr, Action)>,
+    /// named capture groups of `pattern`, bound in the first action
+    pub captures: Vec<CaptureGroup>,
     pub actions: (Action, Vec<Action>),
 }
 
+/// A named capture group in the pattern of a lexer rule.
+#[derive(Debug, Clone)]
+pub struct CaptureGroup {
+    pub name: syn::Ident,
+    pub index: usize,
+    /// whether the group may not participate in a match
+    pub optional: bool,
+}
+
+/// A token variant spelled as a literal, declared with `#[token("...")]`.
+#[derive(Debug, Clone)]
+pub struct TokenLiteral {
+    pub literal: String,
+    pub variant: syn::Path,
+}
+
 #[derive(Debug, Clone)]
 pub struct LexerImpl {
     pub name: syn::Ident,
@@ -21,6 +48,13 @@ pub struct LexerImpl {
     pub vis: syn::Visibility,
     pub inputs: Vec<syn::PatType>,
     pub ret_ty: Option<syn::Type>,
+    pub literals: Vec<TokenLiteral>,
+    /// the action for characters that no rule matches
+    pub error: Option<Action>,
+    /// the mode id of this lexer
+    pub mode: usize,
+    /// other lexers that this lexer can switch to via `push!`
+    pub modes: Vec<(usize, syn::Ident)>,
 }
 
 #[derive(Debug, Clone)]
@@ -31,4 +65,6 @@ pub struct Middle {
     pub items: Vec<syn::Item>,
     pub lexers: Vec<LexerImpl>,
     pub debug: bool,
+    pub longest_match: bool,
+    pub case_insensitive: bool,
 }
@@ -1,3 +1,4 @@
+pub mod derive;
 mod hash;
 pub mod lexer;
 pub mod parser;
@@ -1,12 +1,18 @@
 use proc_macro2::{Span, TokenStream};
-use quote::{format_ident, quote, quote_spanned};
+use quote::{format_ident, quote, quote_spanned, ToTokens};
 use syn::spanned::Spanned;
 
-use crate::parser::middle::{Capture, MemoKind, Middle, ParseOp, ParserImpl, Parsing, Value};
+use crate::{
+    parser::middle::{Capture, IterImpl, MemoKind, Middle, ParseOp, ParserImpl, Parsing, Value},
+    utils::{compile_dfa, dfa_static},
+};
 
 pub struct Context {
     crate_name: TokenStream,
     debug: bool,
+    cst: bool,
+    /// the lexer type, as named in error messages
+    lexer: String,
 }
 
 impl Value {
@@ -45,14 +51,99 @@ impl Capture {
 impl Middle {
     pub fn expand(self) -> Result<TokenStream, TokenStream> {
         let mut result = TokenStream::new();
+        let lexer = self
+            .items
+            .iter()
+            .find_map(|item| match item {
+                syn::Item::Type(ty) if ty.ident == "Lexer" => {
+                    Some(ty.ty.to_token_stream().to_string().replace(' ', ""))
+                }
+                _ => None,
+            })
+            .unwrap_or_else(|| "Lexer".to_string());
         let ctx = Context {
             crate_name: self.crate_name,
             debug: self.debug,
+            cst: self.cst,
+            lexer,
         };
 
+        let mut ast = vec![];
         for parser in self.parsers {
+            ast.extend(parser.ast.clone());
             result.extend(parser.expand(&ctx)?);
         }
+        ast.extend(self.visitors);
+        let defs = ast.iter().map(|ast| &ast.def);
+        let (visit, visit_fn): (Vec<_>, Vec<_>) = ast.iter().map(|ast| ast.visit.clone()).unzip();
+        let (visit_mut, visit_mut_fn): (Vec<_>, Vec<_>) =
+            ast.iter().map(|ast| ast.visit_mut.clone()).unzip();
+        let (fold, fold_fn): (Vec<_>, Vec<_>) = ast.iter().map(|ast| ast.fold.clone()).unzip();
+        let visitors = (!ast.is_empty()).then(|| {
+            quote! {
+                /// Traversing the values of the parsers by reference.
+                #[allow(unused_variables, private_interfaces, clippy::ptr_arg)]
+                pub mod visit {
+                    use super::*;
+
+                    /// Visits each node of the tree, by reference. Every method
+                    /// defaults to the function of the same name, which visits the
+                    /// children of the node. The values of parsers with a type of
+                    /// their own have children only if the type is a struct or an
+                    /// enum of the parser module, in its fields holding values of
+                    /// parsers.
+                    pub trait Visit<'ast> {
+                        #(#visit)*
+                    }
+
+                    #(#visit_fn)*
+                }
+
+                /// Traversing the values of the parsers by mutable reference.
+                #[allow(unused_variables, private_interfaces, clippy::ptr_arg)]
+                pub mod visit_mut {
+                    use super::*;
+
+                    /// Visits each node of the tree, by mutable reference. Every
+                    /// method defaults to the function of the same name, which
+                    /// visits the children of the node, as `Visit` does.
+                    pub trait VisitMut {
+                        #(#visit_mut)*
+                    }
+
+                    #(#visit_mut_fn)*
+                }
+
+                /// Rebuilding the values of the parsers by value.
+                #[allow(unused_variables, private_interfaces)]
+                pub mod fold {
+                    use super::*;
+
+                    /// Rebuilds the tree, node by node. Every method defaults to
+                    /// the function of the same name, which folds the children of
+                    /// the node, as `Visit` visits them. Nodes without children are
+                    /// kept as they are.
+                    pub trait Fold {
+                        #(#fold)*
+                    }
+
+                    #(#fold_fn)*
+                }
+            }
+        });
+        match &self.ast {
+            Some(ast_mod) => result.extend(quote! {
+                /// The types generated for the parsers without a type.
+                pub mod #ast_mod {
+                    #[allow(unused_imports)]
+                    use super::*;
+
+                    #(#defs)*
+                    #visitors
+                }
+            }),
+            None => result.extend(visitors),
+        }
 
         let mod_name = self.mod_name;
         let attrs = self.attrs;
@@ -92,6 +183,7 @@ impl ParserImpl {
         let ret_ty = self.ret_ty;
 
         let crate_name = &ctx.crate_name;
+        let vis = self.vis;
 
         let depends_decl = self.depends.iter().map(|(d, ty)| {
             let name = d.as_ident();
@@ -100,11 +192,35 @@ impl ParserImpl {
         let depends_decl = quote! { #(#depends_decl),* };
         let depends_use = self.depends.iter().map(|(d, _)| d.as_ident());
         let depends_use = quote! { #(#depends_use),* };
-        let depends_def = self.depends.iter().map(|(d, ty)| {
+        // the parsers this one depends on are kept across parses along with
+        // their memos, for incremental reparsing
+        let depends_name = format_ident!("__{}Depends", name, span = Span::mixed_site());
+        let (depends_struct, depends_field, depends_def) = if self.depends.is_empty() {
+            (quote! {}, quote! {}, quote! {})
+        } else {
+            let fields = self.depends.iter().map(|(d, ty)| {
+                let d = d.as_ident();
+                quote! { #d: #ty }
+            });
+            let names = self.depends.iter().map(|(d, _)| d.as_ident());
+            (
+                quote! {
+                    #[derive(Debug, Default)]
+                    struct #depends_name {
+                        #(#fields),*
+                    }
+                },
+                quote! { depends: ::std::cell::OnceCell<::std::boxed::Box<#depends_name>>, },
+                quote! {
+                    let #depends_name { #(#names),* } =
+                        &**self.depends.get_or_init(::std::default::Default::default);
+                },
+            )
+        };
+        let depends_edit = self.depends.iter().map(|(d, _)| {
             let d = d.as_ident();
-            quote! { let #d = &#ty::default(); }
+            quote! { #d.edit_memo(generation, edit.as_ref()); }
         });
-        let depends_def = quote! { #(#depends_def)* };
 
         let state_token = StateToken::new();
         let state = state_token.to_ident();
@@ -123,20 +239,88 @@ impl ParserImpl {
         let cursor_ty = quote! { #crate_name::Cursor };
         let memo_decl = match self.memo {
             MemoKind::None => quote! {},
-            MemoKind::Memorize => quote! { memo: #crate_name::Memo<#cursor_ty, #ret_ty> },
+            MemoKind::Memorize => quote! { memo: #crate_name::Memo<#cursor_ty, #ret_ty>, },
             MemoKind::LeftRec => {
-                quote! { memo: #crate_name::Memo<#cursor_ty, ::std::option::Option<#ret_ty>> }
+                quote! { memo: #crate_name::Memo<#cursor_ty, ::std::option::Option<#ret_ty>>, }
+            }
+        };
+        let edit_memo = match self.memo {
+            // the recorded nodes are not moved by edits, so nothing is reused
+            _ if ctx.cst => {
+                let memo_clear = match self.memo {
+                    MemoKind::None => quote! {},
+                    MemoKind::Memorize | MemoKind::LeftRec => quote! { self.memo.clear(); },
+                };
+                quote! {
+                    fn edit_memo(&self, _: u64, _: ::std::option::Option<&(u64, #crate_name::lexer::Edit)>) {
+                        #memo_clear
+                        self.nodes.clear();
+                    }
+                }
+            }
+            MemoKind::None => quote! {
+                fn edit_memo(&self, _: u64, _: ::std::option::Option<&(u64, #crate_name::lexer::Edit)>) {}
+            },
+            MemoKind::Memorize | MemoKind::LeftRec => {
+                // positions in the values are not moved by edits
+                let edit = if self.positional {
+                    quote! { self.memo.forget_after(edit) }
+                } else {
+                    quote! { self.memo.edit(edit) }
+                };
+                quote! {
+                    fn edit_memo(&self, generation: u64, edit: ::std::option::Option<&(u64, #crate_name::lexer::Edit)>) {
+                        match self.memo.renew(generation, edit) {
+                            Some(edit) => #edit,
+                            None => self.memo.clear(),
+                        }
+                    }
+                }
             }
         };
+        let parse_impl_func = if ctx.cst {
+            quote! {
+                |state| #crate_name::cst::build(state, &self.nodes, Self::NAME, |state| {
+                    self.parse_impl(state, #depends_use)
+                })
+            }
+        } else {
+            quote! { |state| self.parse_impl(state, #depends_use) }
+        };
         let memo_func = match self.memo {
+            MemoKind::None if ctx.cst => quote! {
+                #crate_name::cst::build(#state, &self.nodes, Self::NAME, |state| {
+                    self.parse_impl(state, #depends_use)
+                })
+            },
             MemoKind::None => quote! { self.parse_impl(#state, #depends_use)},
             MemoKind::Memorize => {
-                quote! { #crate_name::memorize(#state, &self.memo, |state| self.parse_impl(state, #depends_use)) }
+                quote! { #crate_name::memorize(#state, &self.memo, #parse_impl_func) }
             }
             MemoKind::LeftRec => {
-                quote! { #crate_name::left_rec(#state, &self.memo, |state| self.parse_impl(state, #depends_use)) }
+                quote! { #crate_name::left_rec(#state, &self.memo, #parse_impl_func) }
             }
         };
+        let memo_func = if ctx.cst {
+            quote! { #crate_name::cst::record(#state, &self.nodes, |#state| #memo_func) }
+        } else {
+            memo_func
+        };
+        let (nodes_decl, parse_cst) = if ctx.cst {
+            (
+                quote! { nodes: #crate_name::cst::Nodes, },
+                quote! {
+                    #vis fn parse_cst<'a>(
+                        &self,
+                        input: impl ::std::convert::Into<#crate_name::ParserState<'a, Lexer>>,
+                    ) -> ::std::result::Result<(#ret_ty, #crate_name::cst::SyntaxNode), #crate_name::Error> {
+                        #crate_name::cst::parse_cst(self, input)
+                    }
+                },
+            )
+        } else {
+            (quote! {}, quote! {})
+        };
         let debug_push = if ctx.debug {
             quote! { #state.push(Self::NAME); }
         } else {
@@ -167,12 +351,58 @@ impl ParserImpl {
         };
 
         let name_str = name.to_string();
-        let vis = self.vis;
+
+        let parse_iter = match self.iter {
+            None => quote! {},
+            Some(IterImpl { item, item_ty, sep }) => {
+                let state_ty = quote! { #crate_name::ParserState<'a, Lexer> };
+                let (sep_def, sep_arg) = match sep {
+                    Some(sep) => (
+                        quote! { let sep = <#sep as ::std::default::Default>::default(); },
+                        quote! {
+                            ::std::option::Option::Some(move |state: &mut #state_ty| {
+                                #crate_name::ParseIt::parse_stream(&sep, state)
+                            })
+                        },
+                    ),
+                    None => (
+                        quote! {},
+                        quote! {
+                            ::std::option::Option::None::<
+                                fn(&mut #state_ty) -> ::std::result::Result<(), #crate_name::Error>
+                            >
+                        },
+                    ),
+                };
+                quote! {
+                    #vis fn parse_iter<'a>(
+                        &self,
+                        input: impl ::std::convert::Into<#state_ty>,
+                    ) -> impl ::std::iter::Iterator<
+                        Item = ::std::result::Result<#item_ty, #crate_name::Error>
+                    > + 'a {
+                        let item = <#item as ::std::default::Default>::default();
+                        #sep_def
+                        #crate_name::stream::Items::new(
+                            ::std::convert::Into::into(input),
+                            move |state: &mut #state_ty| {
+                                #crate_name::ParseIt::parse_stream(&item, state)
+                            },
+                            #sep_arg,
+                        )
+                    }
+                }
+            }
+        };
 
         Ok(quote! {
+            #depends_struct
+
             #[derive(Debug, Default)]
             #vis struct #name {
                 #memo_decl
+                #nodes_decl
+                #depends_field
             }
 
             impl #name {
@@ -180,6 +410,9 @@ impl ParserImpl {
 
                 #parse_impl
                 #parse_memo
+                #edit_memo
+                #parse_iter
+                #parse_cst
             }
 
             impl #crate_name::ParseIt for #name {
@@ -191,8 +424,14 @@ impl ParserImpl {
                     state: &mut #crate_name::ParserState<'a, Lexer>
                 ) -> Result<#ret_ty, ::parse_it::Error> {
                     #depends_def
+                    // reuse the memos of the previous parse only if this
+                    // state is an edit of it
+                    let generation = state.generation();
+                    let edit = state.take_edit();
+                    self.edit_memo(generation, edit.as_ref());
+                    #(#depends_edit)*
                     let result = self.parse_memo(state, #depends_use);
-                    result
+                    result.map_err(|e| state.surface_error(e))
                 }
             }
         })
@@ -215,11 +454,38 @@ impl Parsing {
             let op = match op {
                 ParseOp::Just(c) => {
                     let result = match c {
-                        syn::Lit::Str(lit_str) => {
-                            quote_spanned! { span => #state.parse_str(#lit_str) }
+                        syn::Lit::Str(lit_str) if lit_str.suffix().is_empty() => {
+                            let kind = literal_kind(&lit_str.value(), lit_str.span(), ctx);
+                            quote_spanned! { span =>
+                                match #kind {
+                                    Some(kind) => #state.parse_kind(kind).map(|_| #lit_str),
+                                    None => #state.parse_str(#lit_str),
+                                }
+                            }
+                        }
+                        syn::Lit::Str(lit_str) if lit_str.suffix() == "i" => {
+                            let lit_str = syn::LitStr::new(&lit_str.value(), lit_str.span());
+                            quote_spanned! { span => #state.parse_str_ci(#lit_str) }
+                        }
+                        syn::Lit::Char(lit_char) if lit_char.suffix().is_empty() => {
+                            let kind =
+                                literal_kind(&lit_char.value().to_string(), lit_char.span(), ctx);
+                            quote_spanned! { span =>
+                                match #kind {
+                                    Some(kind) => #state.parse_kind(kind).map(|_| #lit_char),
+                                    None => #state.parse_char(#lit_char),
+                                }
+                            }
+                        }
+                        syn::Lit::Char(lit_char) if lit_char.suffix() == "i" => {
+                            let lit_char = syn::LitChar::new(lit_char.value(), lit_char.span());
+                            quote_spanned! { span => #state.parse_char_ci(#lit_char) }
                         }
-                        syn::Lit::Char(lit_char) => {
-                            quote_spanned! { span => #state.parse_char(#lit_char) }
+                        syn::Lit::Byte(lit_byte) if lit_byte.suffix().is_empty() => {
+                            quote_spanned! { span => #state.parse_byte(#lit_byte) }
+                        }
+                        syn::Lit::ByteStr(lit_bytes) if lit_bytes.suffix().is_empty() => {
+                            quote_spanned! { span => #state.parse_bytes(#lit_bytes) }
                         }
                         _ => {
                             let e = "Unsupported literal";
@@ -228,8 +494,31 @@ impl Parsing {
                     };
                     quote_spanned! { span => let #value = #result; }
                 }
+                ParseOp::Regex(re) => match compile_dfa(&[re.value()], false) {
+                    Some(dfa) => {
+                        let dfa = dfa_static(crate_name, dfa);
+                        quote_spanned! { span =>
+                            let #value = {
+                                #dfa
+                                #state.parse_dfa(&*DFA)
+                            };
+                        }
+                    }
+                    None => {
+                        let re = syn::LitStr::new(&re.value(), re.span());
+                        quote_spanned! { span =>
+                            let #value = {
+                                static REGEX: ::std::sync::LazyLock<#crate_name::lexer::Regex> =
+                                    ::std::sync::LazyLock::new(|| {
+                                        #crate_name::lexer::Regex::new(#re).unwrap()
+                                    });
+                                #state.parse_regex(&REGEX)
+                            };
+                        }
+                    }
+                },
                 ParseOp::JustType(ty) => quote_spanned! { span =>
-                    let #value = #state.parse_literal_type::<#ty>();
+                    let #value = #state.parse_type::<#ty>();
                 },
                 ParseOp::Pat(p, caps) => quote_spanned! { span =>
                     let #value = #state.parse_with(|tt| match tt {
@@ -237,15 +526,30 @@ impl Parsing {
                         _ => None,
                     });
                 },
+                ParseOp::SpannedPat(p, caps) => quote_spanned! { span =>
+                    let #value = #state.parse_spanned_with(|tt| match tt {
+                        #p => Some((#(#caps),*)),
+                        _ => None,
+                    });
+                },
                 ParseOp::Call { parser, depends } => {
                     let parser = parser.as_ident();
                     let depends = depends.iter().map(|d| d.as_ident());
                     quote_spanned! { span => let #value = #parser.parse_memo(#state, #(#depends),*); }
                 }
-                ParseOp::Map { parser, cap, expr } => {
+                ParseOp::Map {
+                    parser,
+                    cap,
+                    expr,
+                    state: state_ident,
+                } => {
                     let parser = parser.to_ident();
                     let capture = cap.to_pat()?;
-                    quote_spanned! { span => let #value = #parser.map(|#capture| #expr); }
+                    let bind_state = state_ident.map(|ident| quote! { let #ident = &*#state; });
+                    quote_spanned! { span =>
+                        #bind_state
+                        let #value = #parser.map(|#capture| #expr);
+                    }
                 }
                 ParseOp::Then { prev, next } => {
                     let prev = prev.to_ident();
@@ -306,8 +610,19 @@ impl Parsing {
                     }
                 }
                 ParseOp::Optional { parser } => {
-                    let parser = parser.expand(state_token, ctx)?;
-                    quote_spanned! { span => let #value = #parser.ok(); }
+                    let fork_token = state_token.fork();
+                    let fork = fork_token.to_ident();
+                    let parser = parser.expand(fork_token, ctx)?;
+                    quote_spanned! { span =>
+                        let #fork = &mut #state.fork();
+                        let #value: ::std::result::Result<_, #crate_name::Error> = match #parser {
+                            Ok(value) => {
+                                #state.advance_to(#fork);
+                                Ok(Some(value))
+                            }
+                            Err(_) => Ok(None),
+                        };
+                    }
                 }
                 ParseOp::LookAhead { parser } => {
                     let fork_token = state_token.fork();
@@ -324,7 +639,7 @@ impl Parsing {
                     let parser = parser.expand(fork_token, ctx)?;
                     quote_spanned! { span =>
                         let #fork = &mut #state.fork();
-                        let #value = if let Ok(value) = #parser {
+                        let #value = if #parser.is_ok() {
                             Err(#state.error())
                         } else {
                             Ok(())
@@ -363,3 +678,23 @@ impl Parsing {
         }})
     }
 }
+
+/// Look up the token kind of a literal terminal at compile time.
+fn literal_kind(literal: &str, span: Span, ctx: &Context) -> TokenStream {
+    // respan the whole expression, so that the error points at the literal
+    let crate_name = ctx.crate_name.clone().into_iter().map(|mut tt| {
+        tt.set_span(span);
+        tt
+    });
+    let crate_name = TokenStream::from_iter(crate_name);
+    let message = format!(
+        "literal `{literal}` is not defined by the lexer `{}`",
+        ctx.lexer
+    );
+    let literal = syn::LitStr::new(literal, span);
+    quote_spanned! { span => {
+        const KIND: ::std::option::Option<usize> =
+            #crate_name::lexer::literal_kind::<Lexer>(#literal, #message);
+        KIND
+    }}
+}
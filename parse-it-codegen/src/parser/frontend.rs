@@ -1,46 +1,68 @@
 use std::{rc::Rc, vec};
 
 use proc_macro2::{Span, TokenStream};
-use quote::{quote, quote_spanned};
+use quote::{format_ident, quote, quote_spanned, ToTokens};
 use syn::{spanned::Spanned, visit_mut::VisitMut};
 
 use crate::{
     hash::{HashMap, HashSet, OrderedMap, OrderedSet},
-    parser::middle::{Capture, MemoKind, Middle, ParserImpl, ParserRef, Parsing},
+    parser::middle::{
+        AstImpl, Capture, IterImpl, MemoKind, Middle, ParserImpl, ParserRef, Parsing,
+    },
     syntax::{Atom, Parser, ParserMod, Part, Production, Rule},
     utils::RewriteSelfVisitor,
 };
 
 #[derive(Default)]
 struct Context {
+    pub crate_name: TokenStream,
     pub parse_macros: Rc<Vec<syn::Path>>,
     pub left_calls: HashMap<syn::Ident, HashSet<syn::Ident>>,
     pub left_recursion: HashSet<syn::Ident>,
     pub direct_depends: HashMap<syn::Ident, OrderedMap<syn::Ident, ParserRef>>,
     pub depends: HashMap<syn::Ident, OrderedMap<syn::Ident, ParserRef>>,
+    /// parsers whose types are generated
+    pub ast: HashSet<syn::Ident>,
+    /// the module of the generated types
+    pub ast_mod: Option<syn::Ident>,
+    /// the types of all parsers, as seen from the module of generated types
+    pub ast_types: HashMap<syn::Ident, syn::Type>,
+    /// the parser whose visitor methods visit the type of each parser
+    pub visited: HashMap<syn::Ident, syn::Ident>,
 }
 
 impl ParserMod {
-    pub fn compile(self) -> Result<Middle, TokenStream> {
+    pub fn compile(mut self) -> Result<Middle, TokenStream> {
+        let crate_name = match &self.config.crate_name {
+            Some(crate_name) => quote! { #crate_name },
+            None => quote! { ::parse_it },
+        };
         let mut ctx = Context {
+            crate_name: crate_name.clone(),
             parse_macros: self.config.parse_macros.clone(),
+            ast_mod: self.config.ast.clone(),
             ..Default::default()
         };
 
+        self.resolve_types(&mut ctx)?;
+        if self.config.ast.is_some() || self.config.visit {
+            self.name_visitors(&mut ctx)?;
+        }
+        self.add_iter_separators();
         self.check_missing_items(&mut ctx)?;
+        let mut iters = self.iter_items()?;
         self.analyze_left_recursion(&mut ctx);
         self.analyze_depends(&mut ctx);
 
-        let crate_name = match &self.config.crate_name {
-            Some(crate_name) => quote! { #crate_name },
-            None => quote! { ::parse_it },
-        };
-
         let mut parsers = Vec::with_capacity(self.parsers.len());
+        let visitors = self.user_visitors(&ctx);
         for parser in self.parsers {
-            let parser = parser.compile(&mut ctx)?;
+            let iter = iters.remove(&parser.name);
+            let mut parser = parser.compile(&mut ctx)?;
+            parser.iter = iter;
             parsers.push(parser);
         }
+        Self::analyze_positions(&ctx, &mut parsers);
 
         let middle = Middle {
             attrs: self.attrs,
@@ -49,10 +71,196 @@ impl ParserMod {
             items: self.items,
             parsers,
             debug: self.config.debug,
+            cst: self.config.cst,
+            ast: self.config.ast,
+            visitors,
         };
         Ok(middle)
     }
 
+    /// Find the parsers whose values may hold positions, directly or through
+    /// the parsers they call.
+    fn analyze_positions(ctx: &Context, parsers: &mut [ParserImpl]) {
+        let direct = parsers
+            .iter()
+            .filter(|p| p.ast.is_some() || p.parser.holds_positions())
+            .map(|p| p.name.clone())
+            .collect::<HashSet<_>>();
+        for parser in parsers {
+            parser.positional = direct.contains(&parser.name)
+                || ctx.depends[&parser.name]
+                    .keys()
+                    .any(|name| direct.contains(name));
+        }
+    }
+
+    /// Name the visitor methods of each type after the first parser of the
+    /// type, and make sure that no two types get methods of the same name.
+    fn name_visitors(&self, ctx: &mut Context) -> Result<(), TokenStream> {
+        let mut firsts = HashMap::<String, syn::Ident>::default();
+        let mut methods = HashMap::<String, syn::Ident>::default();
+        for parser in &self.parsers {
+            let ty = parser.ty.as_ref().expect("types are resolved");
+            let first = firsts
+                .entry(ty.to_token_stream().to_string())
+                .or_insert_with(|| parser.name.clone())
+                .clone();
+            if first == parser.name {
+                let method = snake_case(&parser.name);
+                if let Some(other) = methods.insert(method.clone(), parser.name.clone()) {
+                    let e = format!(
+                        "the visitor methods of `{other}` and `{}` would both be named `visit_{method}`",
+                        parser.name
+                    );
+                    return Err(quote_spanned! { parser.name.span() => compile_error!(#e); });
+                }
+            }
+            ctx.visited.insert(parser.name.clone(), first);
+        }
+        Ok(())
+    }
+
+    /// Generate the visitor methods of the parsers with a type of their own,
+    /// which visit the fields of the type if it is a struct or an enum of the
+    /// parser module, and have no children to visit otherwise.
+    fn user_visitors(&self, ctx: &Context) -> Vec<AstImpl> {
+        let nodes = self
+            .parsers
+            .iter()
+            .filter_map(|p| {
+                let ty = p.ty.as_ref()?.to_token_stream().to_string();
+                Some((ty, ctx.visited.get(&p.name)?.clone()))
+            })
+            .collect::<HashMap<_, _>>();
+        self.parsers
+            .iter()
+            .filter(|p| !ctx.ast.contains(&p.name) && ctx.visited.get(&p.name) == Some(&p.name))
+            .map(|p| {
+                let ty = &ctx.ast_types[&p.name];
+                let (visit, visit_mut, fold) = match self.item_variants(ty) {
+                    Some(variants) => item_visitors(&p.name, ty, variants, &nodes),
+                    None => leaf_visitors(&p.name, ty),
+                };
+                AstImpl {
+                    def: quote! {},
+                    visit,
+                    visit_mut,
+                    fold,
+                }
+            })
+            .collect()
+    }
+
+    /// The variants of a type that is a struct or an enum of the parser module
+    /// without generic parameters, as paths with their fields.
+    fn item_variants(&self, ty: &syn::Type) -> Option<Vec<(TokenStream, &syn::Fields)>> {
+        let syn::Type::Path(syn::TypePath { qself: None, path }) = ty else {
+            return None;
+        };
+        let name = path.get_ident()?;
+        self.items.iter().find_map(|item| match item {
+            syn::Item::Struct(s) if &s.ident == name && s.generics.params.is_empty() => {
+                Some(vec![(quote! { #name }, &s.fields)])
+            }
+            syn::Item::Enum(e) if &e.ident == name && e.generics.params.is_empty() => Some(
+                e.variants
+                    .iter()
+                    .map(|v| {
+                        let variant = &v.ident;
+                        (quote! { #name::#variant }, &v.fields)
+                    })
+                    .collect(),
+            ),
+            _ => None,
+        })
+    }
+
+    /// Give the parsers without a type their generated type, in the module of
+    /// generated types.
+    fn resolve_types(&mut self, ctx: &mut Context) -> Result<(), TokenStream> {
+        for parser in &mut self.parsers {
+            let name = &parser.name;
+            match (&parser.ty, &ctx.ast_mod) {
+                (Some(ty), _) => {
+                    ctx.ast_types.insert(name.clone(), ty.clone());
+                }
+                (None, Some(ast)) => {
+                    ctx.ast.insert(name.clone());
+                    ctx.ast_types.insert(
+                        name.clone(),
+                        syn::parse_quote_spanned! { name.span() => #name },
+                    );
+                    parser.ty = Some(syn::parse_quote_spanned! { name.span() => #ast::#name });
+                }
+                (None, None) => {
+                    let e = "expected `-> Type`, or `#[parse_it(ast)]` to generate it";
+                    return Err(quote_spanned! { name.span() => compile_error!(#e); });
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Turn the separator of each `iter` parser into a hidden parser of its own.
+    fn add_iter_separators(&mut self) {
+        let mut seps = vec![];
+        for parser in &mut self.parsers {
+            let Some(iter) = &mut parser.iter else {
+                continue;
+            };
+            let Some(sep) = iter.sep.take() else {
+                continue;
+            };
+            let span = parser.name.span();
+            let name = format_ident!("__{}Sep", parser.name, span = span);
+            let part = Part {
+                capture: crate::syntax::Capture::NotSpecified,
+                part: sep,
+            };
+            seps.push(Parser {
+                vis: syn::Visibility::Inherited,
+                name: name.clone(),
+                ty: Some(syn::parse_quote_spanned! { span => () }),
+                rules: vec![Rule {
+                    production: Production {
+                        parts: (part, vec![]),
+                    },
+                    action: Some(syn::parse_quote_spanned! { span => () }),
+                }],
+                iter: None,
+            });
+            iter.sep = Some(Atom::NonTerminal(name));
+        }
+        self.parsers.extend(seps);
+    }
+
+    /// Find the item and separator parsers of each `iter` parser.
+    fn iter_items(&self) -> Result<HashMap<syn::Ident, IterImpl>, TokenStream> {
+        let mut iters = HashMap::default();
+        for parser in &self.parsers {
+            let Some(iter) = &parser.iter else {
+                continue;
+            };
+            let Some(item) = self.parsers.iter().find(|p| p.name == iter.item) else {
+                let e = format!("Parser `{}` not found", iter.item);
+                return Err(quote_spanned! { iter.item.span() => compile_error!(#e); });
+            };
+            let sep = match &iter.sep {
+                Some(Atom::NonTerminal(sep)) => Some(sep.clone()),
+                _ => None,
+            };
+            iters.insert(
+                parser.name.clone(),
+                IterImpl {
+                    item: item.name.clone(),
+                    item_ty: item.ty.clone().expect("types are resolved"),
+                    sep,
+                },
+            );
+        }
+        Ok(iters)
+    }
+
     fn check_missing_items(&self, ctx: &mut Context) -> Result<(), TokenStream> {
         let parsers = self
             .parsers
@@ -119,7 +327,7 @@ impl ParserMod {
 }
 
 impl Parser {
-    fn compile(self, ctx: &mut Context) -> Result<ParserImpl, TokenStream> {
+    fn compile(mut self, ctx: &mut Context) -> Result<ParserImpl, TokenStream> {
         let curr = ParserRef::new(&self.name);
         let depends = ctx.depends[&self.name]
             .iter()
@@ -130,10 +338,16 @@ impl Parser {
                 quote_spanned! { self.name.span() => compile_error!("parser must have at least one rule"); },
             );
         }
-        let parser = Parsing::choice_nocap(
-            self.rules.into_iter().map(|rule| rule.compile(ctx)),
-            self.name.span(),
-        )?;
+        let (parser, ast) = if ctx.ast.contains(&self.name) {
+            let (parser, ast) = self.compile_ast(ctx)?;
+            (parser, Some(ast))
+        } else {
+            let parser = Parsing::choice_nocap(
+                self.rules.into_iter().map(|rule| rule.compile(ctx)),
+                self.name.span(),
+            )?;
+            (parser, None)
+        };
 
         let memo = if ctx.left_recursion.contains(&self.name) {
             MemoKind::LeftRec
@@ -147,11 +361,70 @@ impl Parser {
             parser,
             memo,
             vis: self.vis,
-            ret_ty: self.ty,
+            ret_ty: self.ty.expect("types are resolved"),
             depends,
+            iter: None,
+            ast,
+            positional: false,
         })
     }
 
+    /// Compile a parser with a generated type: a struct of the named captures
+    /// if it has a single rule, or else an enum with a variant per rule.
+    fn compile_ast(&mut self, ctx: &mut Context) -> Result<(Parsing, AstImpl), TokenStream> {
+        let name = &self.name;
+        let as_struct = self.rules.len() == 1 && self.rules[0].action.is_none();
+        let mut variants = vec![];
+        let parser = Parsing::choice_nocap(
+            std::mem::take(&mut self.rules).into_iter().map(|rule| {
+                let (parser, variant) = rule.compile_ast(ctx, name, as_struct)?;
+                variants.push(variant);
+                Ok(parser)
+            }),
+            name.span(),
+        )?;
+
+        let vis = ast_vis(&self.vis);
+        let crate_name = &ctx.crate_name;
+        let span_ty = quote! { #crate_name::lexer::Span };
+        let def = if as_struct {
+            match &variants[0].1 {
+                AstFields::Named(fields) => {
+                    let fields = fields.iter().map(|(f, ty, _)| quote! { #vis #f: #ty, });
+                    quote! { #vis struct #name { #(#fields)* #vis span: #span_ty, } }
+                }
+                AstFields::Transparent(ty, _) => quote! { #vis struct #name(#vis #ty); },
+            }
+        } else {
+            let variants = variants.iter().map(|(variant, fields)| match fields {
+                AstFields::Named(fields) => {
+                    let fields = fields.iter().map(|(f, ty, _)| quote! { #f: #ty, });
+                    quote! { #variant { #(#fields)* span: #span_ty, } }
+                }
+                AstFields::Transparent(ty, _) => quote! { #variant(#ty) },
+            });
+            quote! { #vis enum #name { #(#variants),* } }
+        };
+        let def = quote_spanned! { name.span() =>
+            #[derive(Debug, Clone)]
+            #def
+        };
+
+        // the patterns of the type and its variants, binding the fields
+        let paths = variants.iter().map(|(variant, _)| match as_struct {
+            true => quote! { #name },
+            false => quote! { #name::#variant },
+        });
+        let (visit, visit_mut, fold) = ast_visitors(name, paths.zip(&variants).collect());
+        let ast = AstImpl {
+            def,
+            visit,
+            visit_mut,
+            fold,
+        };
+        Ok((parser, ast))
+    }
+
     fn analyze_left_calls<'a>(&self, ctx: &'a mut Context) -> &'a HashSet<syn::Ident> {
         ctx.left_calls
             .entry(self.name.clone())
@@ -181,27 +454,393 @@ impl Parser {
     }
 }
 
-impl Rule {
-    fn compile(mut self, ctx: &mut Context) -> Result<Parsing, TokenStream> {
-        let span = self.action.span();
-        let mut parser = self.production.compile(ctx, span)?;
-
-        let mut visitor = RewriteSelfVisitor::new(ctx.parse_macros.clone());
-        visitor.visit_expr_mut(&mut self.action);
-        if visitor.referred_self {
-            parser.capture = Capture::Named(
-                Box::new(syn::Pat::Ident(syn::PatIdent {
-                    attrs: Vec::new(),
-                    by_ref: None,
-                    mutability: None,
-                    ident: visitor.self_ident,
-                    subpat: None,
-                })),
-                Box::new(parser.capture),
-            );
+/// The fields of a generated type, or of one of its variants.
+enum AstFields {
+    Named(Vec<(syn::Ident, syn::Type, Conv)>),
+    /// a rule that is just another parser
+    Transparent(Box<syn::Type>, Conv),
+}
+
+/// `AddExpr` -> `add_expr`, `HTTPRequest` -> `http_request`
+fn snake_case(name: &syn::Ident) -> String {
+    let chars = name.to_string().chars().collect::<Vec<_>>();
+    let mut snake = String::new();
+    for (i, &ch) in chars.iter().enumerate() {
+        // a word starts after a lowercase letter, or at the last capital of a
+        // run followed by a lowercase letter
+        let starts_word = i > 0
+            && ch.is_uppercase()
+            && (!chars[i - 1].is_uppercase()
+                || chars.get(i + 1).is_some_and(|next| next.is_lowercase()));
+        if starts_word && chars[i - 1] != '_' {
+            snake.push('_');
+        }
+        snake.extend(ch.to_lowercase());
+    }
+    snake
+}
+
+type VisitorImpl = (TokenStream, TokenStream);
+
+/// Generate the trait methods of a generated type, and the functions they
+/// default to, which visit or fold the fields that are generated types.
+fn ast_visitors(
+    name: &syn::Ident,
+    variants: Vec<(TokenStream, &(syn::Ident, AstFields))>,
+) -> (VisitorImpl, VisitorImpl, VisitorImpl) {
+    let mut visit_arms = vec![];
+    let mut visit_mut_arms = vec![];
+    let mut fold_arms = vec![];
+    for (path, (_, fields)) in variants {
+        match fields {
+            AstFields::Named(fields) => {
+                let visited = fields.iter().filter(|(_, _, conv)| conv.has_nodes());
+                let bound = visited.clone().map(|(f, _, _)| f).collect::<Vec<_>>();
+                let visits = visited
+                    .clone()
+                    .map(|(f, _, conv)| conv.visit(quote! { #f }, ""));
+                let visits_mut = visited.map(|(f, _, conv)| conv.visit(quote! { #f }, "_mut"));
+                visit_arms.push(quote! { #path { #(#bound,)* .. } => { #(#visits)* } });
+                visit_mut_arms.push(quote! { #path { #(#bound,)* .. } => { #(#visits_mut)* } });
+
+                let all = fields.iter().map(|(f, _, _)| f).collect::<Vec<_>>();
+                let folds = fields.iter().map(|(f, _, conv)| conv.fold(quote! { #f }));
+                fold_arms.push(quote! {
+                    #path { #(#all,)* span } => #path { #(#all: #folds,)* span }
+                });
+            }
+            AstFields::Transparent(_, conv) => {
+                let bound = if conv.has_nodes() {
+                    quote! { it }
+                } else {
+                    quote! { _ }
+                };
+                let visits = conv.visit(quote! { it }, "");
+                let visits_mut = conv.visit(quote! { it }, "_mut");
+                let folds = conv.fold(quote! { it });
+                visit_arms.push(quote! { #path(#bound) => { #visits } });
+                visit_mut_arms.push(quote! { #path(#bound) => { #visits_mut } });
+                fold_arms.push(quote! { #path(it) => #path(#folds) });
+            }
+        }
+    }
+
+    visitors(
+        name,
+        &quote! { #name },
+        [
+            quote! { match node { #(#visit_arms)* } },
+            quote! { match node { #(#visit_mut_arms)* } },
+            quote! { match node { #(#fold_arms,)* } },
+        ],
+    )
+}
+
+/// Generate the trait methods of a parser with a type of its own held by
+/// generated types, and the functions they default to, which do nothing.
+fn leaf_visitors(name: &syn::Ident, ty: &syn::Type) -> (VisitorImpl, VisitorImpl, VisitorImpl) {
+    visitors(
+        name,
+        &quote! { #ty },
+        [quote! {}, quote! {}, quote! { node }],
+    )
+}
+
+/// Generate the trait methods of a parser whose type is a struct or an enum of
+/// the parser module, and the functions they default to, which visit or fold
+/// the fields holding values of parsers.
+fn item_visitors(
+    name: &syn::Ident,
+    ty: &syn::Type,
+    variants: Vec<(TokenStream, &syn::Fields)>,
+    nodes: &HashMap<String, syn::Ident>,
+) -> (VisitorImpl, VisitorImpl, VisitorImpl) {
+    let mut visit_arms = vec![];
+    let mut visit_mut_arms = vec![];
+    let mut fold_arms = vec![];
+    let mut walked = false;
+    for (path, fields) in &variants {
+        let fields = fields
+            .iter()
+            .enumerate()
+            .map(|(i, field)| {
+                let member = match &field.ident {
+                    Some(f) => quote! { #f },
+                    None => syn::Index::from(i).to_token_stream(),
+                };
+                (
+                    member,
+                    format_ident!("field{}", i),
+                    field_conv(&field.ty, nodes),
+                )
+            })
+            .collect::<Vec<_>>();
+        let visited = fields
+            .iter()
+            .filter(|(_, _, conv)| conv.has_nodes())
+            .collect::<Vec<_>>();
+        if visited.is_empty() {
+            visit_arms.push(quote! { #path { .. } => {} });
+            visit_mut_arms.push(quote! { #path { .. } => {} });
+            fold_arms.push(quote! { node @ #path { .. } => node });
+            continue;
+        }
+        walked = true;
+
+        let bound = visited
+            .iter()
+            .map(|(m, f, _)| quote! { #m: #f })
+            .collect::<Vec<_>>();
+        let visits = visited
+            .iter()
+            .map(|(_, f, conv)| conv.visit(quote! { #f }, ""));
+        let visits_mut = visited
+            .iter()
+            .map(|(_, f, conv)| conv.visit(quote! { #f }, "_mut"));
+        visit_arms.push(quote! { #path { #(#bound,)* .. } => { #(#visits)* } });
+        visit_mut_arms.push(quote! { #path { #(#bound,)* .. } => { #(#visits_mut)* } });
+
+        let all = fields.iter().map(|(m, f, _)| quote! { #m: #f });
+        let folds = fields.iter().map(|(m, f, conv)| {
+            let fold = conv.fold(quote! { #f });
+            quote! { #m: #fold }
+        });
+        fold_arms.push(quote! { #path { #(#all,)* } => #path { #(#folds,)* } });
+    }
+    if !walked {
+        return leaf_visitors(name, ty);
+    }
+
+    visitors(
+        name,
+        &quote! { #ty },
+        [
+            quote! { match node { #(#visit_arms)* } },
+            quote! { match node { #(#visit_mut_arms)* } },
+            quote! { match node { #(#fold_arms,)* } },
+        ],
+    )
+}
+
+/// How a field of a struct or an enum of the parser module is visited, given
+/// the parser whose methods visit each type.
+fn field_conv(ty: &syn::Type, nodes: &HashMap<String, syn::Ident>) -> Conv {
+    if let Some(name) = nodes.get(&ty.to_token_stream().to_string()) {
+        return Conv::Node {
+            name: name.clone(),
+            boxed: false,
+        };
+    }
+    let syn::Type::Path(syn::TypePath { qself: None, path }) = ty else {
+        return Conv::Same;
+    };
+    let Some(last) = path.segments.last() else {
+        return Conv::Same;
+    };
+    let syn::PathArguments::AngleBracketed(args) = &last.arguments else {
+        return Conv::Same;
+    };
+    let (Some(syn::GenericArgument::Type(inner)), 1) = (args.args.first(), args.args.len()) else {
+        return Conv::Same;
+    };
+    let conv = field_conv(inner, nodes);
+    match last.ident.to_string().as_str() {
+        "Box" => match conv {
+            Conv::Node { name, boxed: false } => Conv::Node { name, boxed: true },
+            _ => Conv::Same,
+        },
+        "Vec" => Conv::Each(Box::new(conv)),
+        "Option" => Conv::Map(Box::new(conv)),
+        _ => Conv::Same,
+    }
+}
+
+/// Generate the trait methods visiting and folding the type of a parser, and
+/// the functions they default to, given the bodies of the functions.
+fn visitors(
+    name: &syn::Ident,
+    ty: &TokenStream,
+    [visit_body, visit_mut_body, fold_body]: [TokenStream; 3],
+) -> (VisitorImpl, VisitorImpl, VisitorImpl) {
+    let snake = snake_case(name);
+    let visit = format_ident!("visit_{}", snake);
+    let visit_mut = format_ident!("visit_{}_mut", snake);
+    let fold = format_ident!("fold_{}", snake);
+
+    let visit = (
+        quote! {
+            fn #visit(&mut self, node: &'ast #ty) {
+                #visit(self, node)
+            }
+        },
+        quote! {
+            pub fn #visit<'ast, V: Visit<'ast> + ?Sized>(v: &mut V, node: &'ast #ty) {
+                #visit_body
+            }
+        },
+    );
+    let visit_mut = (
+        quote! {
+            fn #visit_mut(&mut self, node: &mut #ty) {
+                #visit_mut(self, node)
+            }
+        },
+        quote! {
+            pub fn #visit_mut<V: VisitMut + ?Sized>(v: &mut V, node: &mut #ty) {
+                #visit_mut_body
+            }
+        },
+    );
+    let fold = (
+        quote! {
+            fn #fold(&mut self, node: #ty) -> #ty {
+                #fold(self, node)
+            }
+        },
+        quote! {
+            pub fn #fold<F: Fold + ?Sized>(f: &mut F, node: #ty) -> #ty {
+                #fold_body
+            }
+        },
+    );
+    (visit, visit_mut, fold)
+}
+
+/// The visibility of a generated type, from the `ast` module.
+fn ast_vis(vis: &syn::Visibility) -> TokenStream {
+    match vis {
+        syn::Visibility::Inherited => quote! { pub(super) },
+        syn::Visibility::Restricted(r) if r.in_token.is_none() && r.path.is_ident("super") => {
+            quote! { pub(in super::super) }
         }
+        _ => quote! { #vis },
+    }
+}
+
+/// Map the result of a rule with its action, which may refer to the result as
+/// `self`, and to the state as `state!()`.
+fn map_action(mut parser: Parsing, mut action: syn::Expr, ctx: &Context) -> Parsing {
+    let mut visitor = RewriteSelfVisitor::new(ctx.parse_macros.clone());
+    visitor.visit_expr_mut(&mut action);
+
+    if visitor.referred_self {
+        parser.capture = Capture::Named(
+            Box::new(syn::Pat::Ident(syn::PatIdent {
+                attrs: Vec::new(),
+                by_ref: None,
+                mutability: None,
+                ident: visitor.self_ident,
+                subpat: None,
+            })),
+            Box::new(parser.capture),
+        );
+    }
+
+    let state = visitor.referred_state.then_some(visitor.state_ident);
+    parser.map(action, state)
+}
+
+impl Rule {
+    fn compile(self, ctx: &mut Context) -> Result<Parsing, TokenStream> {
+        let Some(action) = self.action else {
+            let e = "expected `=>` and an action";
+            let span = self.production.parts.0.span();
+            return Err(quote_spanned! { span => compile_error!(#e); });
+        };
+        let span = action.span();
+        let parser = self.production.compile(ctx, span)?;
+        Ok(map_action(parser, action, ctx))
+    }
+
+    /// Compile a rule of a parser with a generated type, whose action builds
+    /// the named captures into the type.
+    fn compile_ast(
+        self,
+        ctx: &mut Context,
+        curr: &syn::Ident,
+        as_struct: bool,
+    ) -> Result<(Parsing, (syn::Ident, AstFields)), TokenStream> {
+        let transparent = match &self.production.parts {
+            (
+                Part {
+                    capture: crate::syntax::Capture::NotSpecified,
+                    part: Atom::NonTerminal(name),
+                },
+                rest,
+            ) if rest.is_empty() => Some(name.clone()),
+            _ => None,
+        };
+        let variant = match &self.action {
+            None if as_struct => curr.clone(),
+            None => transparent.clone().ok_or_else(|| {
+                let e = "name the variant of this rule with `=> Variant`";
+                let span = self.production.parts.0.span();
+                quote_spanned! { span => compile_error!(#e); }
+            })?,
+            Some(syn::Expr::Path(path)) if path.path.get_ident().is_some() => {
+                path.path.get_ident().unwrap().clone()
+            }
+            Some(action) => {
+                let e = "rules of parsers with a generated type can only name their variant";
+                return Err(quote_spanned! { action.span() => compile_error!(#e); });
+            }
+        };
+        let ast = ctx.ast_mod.as_ref().expect("types are generated");
+        let path = if as_struct {
+            quote! { #ast::#curr }
+        } else {
+            quote! { #ast::#curr::#variant }
+        };
+
+        let mut atoms = HashMap::default();
+        self.production.named_atoms(&mut atoms);
+        let mut types = atoms
+            .into_iter()
+            .map(|(name, atom)| (name, atom.ast_type(ctx, curr, true)))
+            .collect::<HashMap<_, _>>();
+        let span = variant.span();
+        let parser = self.production.compile(ctx, span)?;
+        let names = parser.capture.names();
 
-        Ok(parser.map(self.action))
+        let (fields, action) = if let (Some(name), true) = (transparent, names.is_empty()) {
+            let atom = Atom::NonTerminal(name);
+            let (ty, conv) = atom.ast_type(ctx, curr, true).expect("parsers have types");
+            let value = conv.apply(quote! { self });
+            (
+                AstFields::Transparent(Box::new(ty), conv),
+                quote! { #path(#value) },
+            )
+        } else {
+            let mut fields = vec![];
+            let mut values = vec![];
+            for pat in names {
+                let syn::Pat::Ident(pat) = pat else {
+                    let e = "named captures must be idents to become fields";
+                    return Err(quote_spanned! { pat.span() => compile_error!(#e); });
+                };
+                let f = &pat.ident;
+                if f == "span" {
+                    let e = "`span` is the field of the span of the rule";
+                    return Err(quote_spanned! { f.span() => compile_error!(#e); });
+                }
+                let Some((ty, conv)) = types.remove(f).flatten() else {
+                    let e = "cannot derive the type of this capture";
+                    return Err(quote_spanned! { f.span() => compile_error!(#e); });
+                };
+                values.push(conv.apply(quote! { #f }));
+                fields.push((f.clone(), ty, conv));
+            }
+            let names = fields.iter().map(|(f, _, _)| f);
+            let action = quote! {
+                #path {
+                    #(#names: #values,)*
+                    span: state!().rule_span(),
+                }
+            };
+            (AstFields::Named(fields), action)
+        };
+        let action = syn::parse2(action).expect("the action is an expression");
+        Ok((map_action(parser, action, ctx), (variant, fields)))
     }
 
     fn left_calls(&self) -> impl Iterator<Item = syn::Ident> + '_ {
@@ -288,7 +927,17 @@ impl Atom {
     fn compile(self, ctx: &mut Context, span: Span) -> Result<Parsing, TokenStream> {
         match self {
             Atom::Terminal(lit) => Ok(Parsing::just(lit, span)),
+            Atom::RegexTerminal(lit) => {
+                if let Err(e) = regex_syntax::parse(&lit.value()) {
+                    let e = format!("Invalid regex pattern: {e}");
+                    return Err(quote_spanned! { lit.span() => compile_error!(#e); });
+                }
+                Ok(Parsing::just_regex(lit, span))
+            }
             Atom::PatTerminal(pat) => Ok(Parsing::just_pat(pat, span)),
+            Atom::SpannedPatTerminal(pat, span_ident) => {
+                Ok(Parsing::just_spanned_pat(pat, span_ident, span))
+            }
             Atom::TypePterminal(ty) => Ok(Parsing::just_type(ty, span)),
             Atom::NonTerminal(name) => {
                 let depends = ctx.depends.get(&name).ok_or_else(|| {
@@ -337,8 +986,11 @@ impl Atom {
     /// Whether this atom must make progress when parsing.
     fn must_progress(&self) -> bool {
         match self {
+            Atom::RegexTerminal(lit) => regex_syntax::parse(&lit.value())
+                .is_ok_and(|hir| hir.properties().minimum_len().is_some_and(|len| len > 0)),
             Atom::Terminal(_)
             | Atom::PatTerminal(_)
+            | Atom::SpannedPatTerminal(..)
             | Atom::TypePterminal(_)
             | Atom::NonTerminal(_) => true,
             Atom::Repeat(_) | Atom::Optional(_) | Atom::LookAhead(_) | Atom::LookAheadNot(_) => {
@@ -356,7 +1008,9 @@ impl Atom {
     fn may_progress(&self) -> bool {
         match self {
             Atom::Terminal(_)
+            | Atom::RegexTerminal(_)
             | Atom::PatTerminal(_)
+            | Atom::SpannedPatTerminal(..)
             | Atom::TypePterminal(_)
             | Atom::NonTerminal(_) => true,
             Atom::LookAhead(_) | Atom::LookAheadNot(_) => false,
@@ -368,3 +1022,222 @@ impl Atom {
         }
     }
 }
+
+/// How a captured value is turned into the field of a generated type, which
+/// is also how the field is visited.
+enum Conv {
+    Same,
+    /// `&str` into `String`, `&[u8]` into `Vec<u8>`
+    Owned,
+    /// the value of another parser, visited by its own method
+    Node {
+        name: syn::Ident,
+        boxed: bool,
+    },
+    Each(Box<Conv>),
+    Map(Box<Conv>),
+}
+
+impl Conv {
+    fn apply(&self, value: TokenStream) -> TokenStream {
+        match self {
+            Conv::Same | Conv::Node { boxed: false, .. } => value,
+            Conv::Owned => quote! { #value.to_owned() },
+            Conv::Node { boxed: true, .. } => {
+                quote! { ::std::boxed::Box::new(#value) }
+            }
+            Conv::Each(conv) => {
+                let conv = conv.apply(quote! { v });
+                quote! { #value.into_iter().map(|v| #conv).collect::<::std::vec::Vec<_>>() }
+            }
+            Conv::Map(conv) => {
+                let conv = conv.apply(quote! { v });
+                quote! { #value.map(|v| #conv) }
+            }
+        }
+    }
+
+    /// Whether the field holds values of parsers to visit.
+    fn has_nodes(&self) -> bool {
+        match self {
+            Conv::Node { .. } => true,
+            Conv::Each(conv) | Conv::Map(conv) => conv.has_nodes(),
+            Conv::Same | Conv::Owned => false,
+        }
+    }
+
+    /// Visit the field, given a reference to it.
+    fn visit(&self, value: TokenStream, suffix: &str) -> TokenStream {
+        match self {
+            Conv::Node { name, .. } => {
+                let method = format_ident!("visit_{}{}", snake_case(name), suffix);
+                quote! { v.#method(#value); }
+            }
+            Conv::Each(conv) if conv.has_nodes() => {
+                let visit = conv.visit(quote! { it }, suffix);
+                quote! { for it in #value { #visit } }
+            }
+            Conv::Map(conv) if conv.has_nodes() => {
+                let visit = conv.visit(quote! { it }, suffix);
+                quote! { if let Some(it) = #value { #visit } }
+            }
+            _ => quote! {},
+        }
+    }
+
+    /// Fold the field, given its value.
+    fn fold(&self, value: TokenStream) -> TokenStream {
+        match self {
+            Conv::Node { name, boxed } => {
+                let method = format_ident!("fold_{}", snake_case(name));
+                if *boxed {
+                    quote! { ::std::boxed::Box::new(f.#method(*#value)) }
+                } else {
+                    quote! { f.#method(#value) }
+                }
+            }
+            Conv::Each(conv) if conv.has_nodes() => {
+                let fold = conv.fold(quote! { it });
+                quote! { #value.into_iter().map(|it| #fold).collect() }
+            }
+            Conv::Map(conv) if conv.has_nodes() => {
+                let fold = conv.fold(quote! { it });
+                quote! { #value.map(|it| #fold) }
+            }
+            _ => value,
+        }
+    }
+}
+
+impl Production {
+    /// Collect the atoms of the named captures, down the sub-productions that
+    /// keep the names.
+    fn named_atoms<'a>(&'a self, atoms: &mut HashMap<syn::Ident, &'a Atom>) {
+        for part in self.parts() {
+            if let crate::syntax::Capture::Named(pat) = &part.capture {
+                if let syn::Pat::Ident(pat) = pat.as_ref() {
+                    atoms.entry(pat.ident.clone()).or_insert(&part.part);
+                }
+            }
+            match &part.part {
+                Atom::Sub(p) => p.named_atoms(atoms),
+                Atom::Choice(first, rest) => {
+                    first.named_atoms(atoms);
+                    for p in rest {
+                        p.named_atoms(atoms);
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// The type of the value of this production, if it has a single part that
+    /// is not silent.
+    fn ast_type(&self, ctx: &Context, curr: &syn::Ident, boxed: bool) -> Option<(syn::Type, Conv)> {
+        let mut loud = self.parts().filter(|p| !p.is_silent());
+        match (loud.next(), loud.next()) {
+            (Some(part), None) if part.is_anonymous() => part.part.ast_type(ctx, curr, boxed),
+            _ => None,
+        }
+    }
+}
+
+impl Part {
+    /// The span of the part, for error messages.
+    fn span(&self) -> Span {
+        match &self.capture {
+            crate::syntax::Capture::Named(pat) => pat.span(),
+            _ => self.part.span(),
+        }
+    }
+
+    fn is_anonymous(&self) -> bool {
+        !matches!(self.capture, crate::syntax::Capture::Named(_))
+    }
+
+    /// Whether the part captures nothing, unless marked with `@`.
+    fn is_silent(&self) -> bool {
+        matches!(self.capture, crate::syntax::Capture::NotSpecified) && self.part.is_silent()
+    }
+}
+
+impl Atom {
+    fn span(&self) -> Span {
+        match self {
+            Atom::Terminal(lit) => lit.span(),
+            Atom::RegexTerminal(lit) => lit.span(),
+            Atom::PatTerminal(pat) | Atom::SpannedPatTerminal(pat, _) => pat.span(),
+            Atom::TypePterminal(ty) => ty.span(),
+            Atom::NonTerminal(name) => name.span(),
+            Atom::Sub(p) | Atom::Choice(p, _) => p.parts.0.span(),
+            Atom::Repeat(p)
+            | Atom::Repeat1(p)
+            | Atom::Optional(p)
+            | Atom::LookAhead(p)
+            | Atom::LookAheadNot(p) => p.span(),
+        }
+    }
+
+    fn is_silent(&self) -> bool {
+        match self {
+            Atom::Terminal(_) | Atom::LookAhead(_) | Atom::LookAheadNot(_) => true,
+            Atom::Repeat(p) | Atom::Repeat1(p) | Atom::Optional(p) => p.is_silent(),
+            Atom::Sub(p) => p.parts().all(Part::is_silent),
+            Atom::Choice(first, _) => first.parts().all(Part::is_silent),
+            _ => false,
+        }
+    }
+
+    /// The type of a capture of this atom in a generated type, and how to
+    /// convert the captured value. Parsers that may contain the current one
+    /// are boxed, unless in a collection.
+    fn ast_type(&self, ctx: &Context, curr: &syn::Ident, boxed: bool) -> Option<(syn::Type, Conv)> {
+        let result = match self {
+            Atom::Terminal(syn::Lit::Char(_)) => (syn::parse_quote! { char }, Conv::Same),
+            Atom::Terminal(syn::Lit::Byte(_)) => (syn::parse_quote! { u8 }, Conv::Same),
+            Atom::Terminal(syn::Lit::Str(_)) | Atom::RegexTerminal(_) => {
+                (syn::parse_quote! { ::std::string::String }, Conv::Owned)
+            }
+            Atom::Terminal(syn::Lit::ByteStr(_)) => {
+                (syn::parse_quote! { ::std::vec::Vec<u8> }, Conv::Owned)
+            }
+            Atom::TypePterminal(ty) => (ty.clone(), Conv::Same),
+            Atom::NonTerminal(name) => {
+                let ty = ctx.ast_types.get(name)?.clone();
+                let recursive = name == curr
+                    || ctx
+                        .depends
+                        .get(name)
+                        .is_some_and(|depends| depends.contains_key(curr));
+                let boxed = boxed && recursive;
+                let conv = Conv::Node {
+                    name: ctx.visited.get(name).unwrap_or(name).clone(),
+                    boxed,
+                };
+                if boxed {
+                    (syn::parse_quote! { ::std::boxed::Box<#ty> }, conv)
+                } else {
+                    (ty, conv)
+                }
+            }
+            Atom::Repeat(p) | Atom::Repeat1(p) => {
+                let (ty, conv) = p.ast_type(ctx, curr, false)?;
+                (
+                    syn::parse_quote! { ::std::vec::Vec<#ty> },
+                    Conv::Each(Box::new(conv)),
+                )
+            }
+            Atom::Optional(p) => {
+                let (ty, conv) = p.ast_type(ctx, curr, boxed)?;
+                (
+                    syn::parse_quote! { ::std::option::Option<#ty> },
+                    Conv::Map(Box::new(conv)),
+                )
+            }
+            Atom::Sub(p) | Atom::Choice(p, _) => p.ast_type(ctx, curr, boxed)?,
+            _ => return None,
+        };
+        Some(result)
+    }
+}
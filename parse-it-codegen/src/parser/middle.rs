@@ -71,6 +71,23 @@ impl Capture {
         }
     }
 
+    /// The named captures bound by this capture, in order.
+    pub fn names(&self) -> Vec<&syn::Pat> {
+        match self {
+            Capture::Loud | Capture::Slient | Capture::TupleVec(_) => vec![],
+            Capture::Named(p, c) => {
+                let mut names = vec![p.as_ref()];
+                names.extend(c.names());
+                names
+            }
+            Capture::Tuple(c1, c2) => {
+                let mut names = c1.names();
+                names.extend(c2.names());
+                names
+            }
+        }
+    }
+
     pub fn to_anonymous(&self) -> Capture {
         if self.is_loud() {
             Capture::Loud
@@ -133,6 +150,28 @@ impl Parsing {
             .expect("parser is empty")
     }
 
+    /// Whether the parsed value may hold positions in the source text, by a
+    /// spanned terminal or `state!()`, not counting the called parsers.
+    pub fn holds_positions(&self) -> bool {
+        self.values.values().any(|op| match op {
+            ParseOp::SpannedPat(..) => true,
+            ParseOp::Map { state, .. } => state.is_some(),
+            ParseOp::Then { next: p, .. }
+            | ParseOp::ThenIgnore { next: p, .. }
+            | ParseOp::IgnoreThen { next: p, .. }
+            | ParseOp::Repeat { parser: p, .. }
+            | ParseOp::Optional { parser: p }
+            | ParseOp::LookAhead { parser: p }
+            | ParseOp::LookAheadNot { parser: p } => p.holds_positions(),
+            ParseOp::Choice { parsers } => parsers.iter().any(Parsing::holds_positions),
+            ParseOp::Just(_)
+            | ParseOp::Regex(_)
+            | ParseOp::JustType(_)
+            | ParseOp::Pat(..)
+            | ParseOp::Call { .. } => false,
+        })
+    }
+
     fn push(mut self, op: ParseOp) -> Self {
         self.values.insert(Value::next(), op);
         self
@@ -142,6 +181,10 @@ impl Parsing {
         Self::from_op(ParseOp::Just(c), Capture::Slient, span)
     }
 
+    pub fn just_regex(re: syn::LitStr, span: Span) -> Self {
+        Self::from_op(ParseOp::Regex(re), Capture::Loud, span)
+    }
+
     pub fn just_pat(p: syn::Pat, span: Span) -> Self {
         let captures = PatVistor::collect_captures(&p);
         let captures: Vec<syn::Ident> = captures.into_iter().collect();
@@ -152,8 +195,19 @@ impl Parsing {
         )
     }
 
+    pub fn just_spanned_pat(p: syn::Pat, span_ident: syn::Ident, span: Span) -> Self {
+        let p = syn::parse_quote! { (#p, #span_ident) };
+        let captures = PatVistor::collect_captures(&p);
+        let captures: Vec<syn::Ident> = captures.into_iter().collect();
+        Self::from_op(
+            ParseOp::SpannedPat(p.clone(), captures.clone()),
+            Capture::TupleVec(captures),
+            span,
+        )
+    }
+
     pub fn just_type(ty: syn::Type, span: Span) -> Self {
-        Self::from_op(ParseOp::JustType(ty), Capture::Slient, span)
+        Self::from_op(ParseOp::JustType(ty), Capture::Loud, span)
     }
 
     pub fn call(name: syn::Ident, depends: Vec<ParserRef>, span: Span) -> Self {
@@ -167,13 +221,14 @@ impl Parsing {
         )
     }
 
-    pub fn map(self, f: syn::Expr) -> Self {
+    pub fn map(self, f: syn::Expr, state: Option<syn::Ident>) -> Self {
         let parser = self.result();
         let capture = self.capture.clone();
         self.push(ParseOp::Map {
             parser,
             cap: capture,
             expr: f,
+            state,
         })
     }
 
@@ -260,11 +315,27 @@ impl Parsing {
 
 pub enum ParseOp {
     /// ```ignore
-    /// {state}.parse({lit})
+    /// match literal_kind::<Lexer>({lit}, "literal {lit} is not defined by ...") {
+    ///     Some(kind) => {state}.parse_kind(kind),
+    ///     None => {state}.parse({lit}),
+    /// }
+    /// {state}.parse_ci({lit}) // with the `i` suffix
     /// ```
     Just(syn::Lit),
     /// ```ignore
-    /// {state}.parse_literal_type::<{ty}>()
+    /// static DFA: LazyLock<Dfa> = LazyLock::new(|| load_dfa({bytes}));
+    /// {state}.parse_dfa(&DFA)
+    /// ```
+    ///
+    /// or, if the regex does not fit in a DFA,
+    ///
+    /// ```ignore
+    /// static REGEX: LazyLock<Regex> = LazyLock::new(|| Regex::new({re}).unwrap());
+    /// {state}.parse_regex(&REGEX)
+    /// ```
+    Regex(syn::LitStr),
+    /// ```ignore
+    /// {state}.parse_type::<{ty}>()
     /// ```
     JustType(syn::Type),
     /// ```ignore
@@ -275,6 +346,13 @@ pub enum ParseOp {
     /// ```
     Pat(syn::Pat, Vec<syn::Ident>),
     /// ```ignore
+    /// {state}.parse_spanned_with(|tt| match tt {
+    ///     ({pat}, {span}) => Some(({..cap})),
+    ///     _ => None,
+    /// })
+    /// ```
+    SpannedPat(syn::Pat, Vec<syn::Ident>),
+    /// ```ignore
     /// {parser}.parse_memo({state}, {..depends})
     /// ```
     Call {
@@ -282,12 +360,14 @@ pub enum ParseOp {
         depends: Vec<ParserRef>,
     },
     /// ```ignore
+    /// let {state_ident} = &*{state}; // if `state!()` is referred
     /// {parser}.map(|{cap}| {f})
     /// ```
     Map {
         parser: Value,
         cap: Capture,
         expr: syn::Expr,
+        state: Option<syn::Ident>,
     },
     /// ```ignore
     /// match {prev} {
@@ -328,7 +408,14 @@ pub enum ParseOp {
         at_least: usize,
     },
     /// ```ignore
-    /// {parser}.ok()
+    /// let fork = &{state}.fork();
+    /// match {parser/fork} {
+    ///     Ok(value) => {
+    ///         {state}.advance_to(fork);
+    ///         Ok(Some(value))
+    ///     }
+    ///     Err(_) => Ok(None),
+    /// }
     /// ```
     Optional { parser: Box<Parsing> },
     /// ```ignore
@@ -377,6 +464,29 @@ pub struct ParserImpl {
     pub vis: syn::Visibility,
     pub ret_ty: syn::Type,
     pub depends: Vec<(ParserRef, syn::Ident)>,
+    pub iter: Option<IterImpl>,
+    /// The generated type, in `#[parse_it(ast)]` mode.
+    pub ast: Option<AstImpl>,
+    /// Whether the values may hold positions in the source text, which are
+    /// not moved by edits.
+    pub positional: bool,
+}
+
+/// A generated type, and its part of the visitors: a trait method and the
+/// function it defaults to, for each of `Visit`, `VisitMut` and `Fold`.
+#[derive(Clone)]
+pub struct AstImpl {
+    pub def: TokenStream,
+    pub visit: (TokenStream, TokenStream),
+    pub visit_mut: (TokenStream, TokenStream),
+    pub fold: (TokenStream, TokenStream),
+}
+
+/// The parsers applied by the generated `parse_iter` method.
+pub struct IterImpl {
+    pub item: syn::Ident,
+    pub item_ty: syn::Type,
+    pub sep: Option<syn::Ident>,
 }
 
 #[derive(Clone, PartialEq, Eq, Hash)]
@@ -407,4 +517,10 @@ pub struct Middle {
     pub items: Vec<syn::Item>,
     pub parsers: Vec<ParserImpl>,
     pub debug: bool,
+    pub cst: bool,
+    /// The module of the generated types, in `#[parse_it(ast)]` mode.
+    pub ast: Option<syn::Ident>,
+    /// The visitor methods of the parsers with a type of their own, in
+    /// `#[parse_it(ast)]` or `#[parse_it(visit)]` mode.
+    pub visitors: Vec<AstImpl>,
 }
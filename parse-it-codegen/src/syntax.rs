@@ -1,6 +1,8 @@
 use std::rc::Rc;
 
-use syn::{parse::discouraged::Speculative, punctuated::Punctuated, Attribute, Token};
+use syn::{
+    parse::discouraged::Speculative, punctuated::Punctuated, spanned::Spanned, Attribute, Token,
+};
 
 #[derive(Debug)]
 pub struct ParseIt {
@@ -69,26 +71,78 @@ pub enum Mod {
     Lexer(LexerMod),
 }
 
+/// The macros whose arguments may refer to `self` in actions, by default.
+fn default_parse_macros() -> Rc<Vec<syn::Path>> {
+    Rc::new(vec![
+        syn::parse_quote! { print },
+        syn::parse_quote! { println },
+        syn::parse_quote! { eprint },
+        syn::parse_quote! { eprintln },
+        syn::parse_quote! { format },
+        syn::parse_quote! { dbg },
+    ])
+}
+
+/// Parse an option shared by parser and lexer modules: `crate`,
+/// `parse_macros` or `debug`. Returns `false` if the option is another one.
+fn parse_common_option(
+    meta: &syn::meta::ParseNestedMeta,
+    crate_name: &mut Option<syn::Path>,
+    parse_macros: &mut Rc<Vec<syn::Path>>,
+    debug: &mut bool,
+) -> syn::Result<bool> {
+    if meta.path.is_ident("crate") {
+        let value = meta.value()?;
+        let value = value.parse::<syn::LitStr>()?;
+        *crate_name = Some(
+            value
+                .parse()
+                .map_err(|_| syn::Error::new_spanned(value, "expected a valid path"))?,
+        );
+    } else if meta.path.is_ident("parse_macros") {
+        let value = meta.value()?;
+        let value = value.parse::<syn::LitStr>()?;
+        *parse_macros = Rc::new(
+            value
+                .parse_with(Punctuated::<syn::Path, Token![,]>::parse_terminated)
+                .map_err(|_| {
+                    syn::Error::new_spanned(value, "expected a list of paths separated by commas")
+                })?
+                .into_iter()
+                .collect(),
+        );
+    } else if meta.path.is_ident("debug") {
+        let value = meta.value()?;
+        let value = value.parse::<syn::LitBool>()?;
+        *debug = value.value;
+    } else {
+        return Ok(false);
+    }
+    Ok(true)
+}
+
 #[derive(Debug)]
 pub struct ParserConfig {
     pub crate_name: Option<syn::Path>,
     pub parse_macros: Rc<Vec<syn::Path>>,
     pub debug: bool,
+    pub cst: bool,
+    /// The module of the generated types, in `#[parse_it(ast)]` mode.
+    pub ast: Option<syn::Ident>,
+    /// Whether to generate the visitors of the types of the parsers, which
+    /// `#[parse_it(ast)]` mode always does.
+    pub visit: bool,
 }
 
 impl Default for ParserConfig {
     fn default() -> Self {
         Self {
             crate_name: None,
-            parse_macros: Rc::new(vec![
-                syn::parse_quote! { print },
-                syn::parse_quote! { println },
-                syn::parse_quote! { eprint },
-                syn::parse_quote! { eprintln },
-                syn::parse_quote! { format },
-                syn::parse_quote! { dbg },
-            ]),
+            parse_macros: default_parse_macros(),
             debug: false,
+            cst: false,
+            ast: None,
+            visit: false,
         }
     }
 }
@@ -113,31 +167,43 @@ impl ParserMod {
         for attr in attrs {
             if attr.path().is_ident("parse_it") {
                 attr.parse_nested_meta(|meta| {
-                    if meta.path.is_ident("crate") {
-                        let value = meta.value()?;
-                        let value = value.parse::<syn::LitStr>()?;
-                        config.crate_name = Some(value.parse().map_err(|_| {
-                            syn::Error::new_spanned(value, "expected a valid path")
-                        })?);
-                    } else if meta.path.is_ident("parse_macros") {
-                        let value = meta.value()?;
-                        let value = value.parse::<syn::LitStr>()?;
-                        config.parse_macros = Rc::new(
-                            value
-                                .parse_with(Punctuated::<syn::Path, Token![,]>::parse_terminated)
-                                .map_err(|_| {
-                                    syn::Error::new_spanned(
-                                        value,
-                                        "expected a list of paths separated by commas",
-                                    )
-                                })?
-                                .into_iter()
-                                .collect(),
-                        );
-                    } else if meta.path.is_ident("debug") {
-                        let value = meta.value()?;
-                        let value = value.parse::<syn::LitBool>()?;
-                        config.debug = value.value;
+                    if parse_common_option(
+                        &meta,
+                        &mut config.crate_name,
+                        &mut config.parse_macros,
+                        &mut config.debug,
+                    )? {
+                        return Ok(());
+                    }
+                    if meta.path.is_ident("cst") {
+                        config.cst = if meta.input.peek(Token![=]) {
+                            meta.value()?.parse::<syn::LitBool>()?.value
+                        } else {
+                            true
+                        };
+                    } else if meta.path.is_ident("visit") {
+                        config.visit = if meta.input.peek(Token![=]) {
+                            meta.value()?.parse::<syn::LitBool>()?.value
+                        } else {
+                            true
+                        };
+                    } else if meta.path.is_ident("ast") {
+                        // `ast = "name"` names the module of the generated types
+                        let default = syn::Ident::new("ast", meta.path.span());
+                        config.ast = if meta.input.peek(Token![=]) {
+                            match meta.value()?.parse::<syn::Lit>()? {
+                                syn::Lit::Bool(value) => value.value.then_some(default),
+                                syn::Lit::Str(value) => Some(value.parse().map_err(|_| {
+                                    syn::Error::new_spanned(&value, "expected a module name")
+                                })?),
+                                value => Err(syn::Error::new_spanned(
+                                    value,
+                                    "expected a boolean or a module name",
+                                ))?,
+                            }
+                        } else {
+                            Some(default)
+                        };
                     } else {
                         Err(syn::Error::new_spanned(meta.path, "unknown attribute"))?
                     }
@@ -171,22 +237,66 @@ impl ParserMod {
 }
 
 /// ```text
-/// Parser ::= Vis Name '->' Type '{' Rule* '}'
+/// Parser ::= Attr* Vis Name ('->' Type)? '{' Rule* '}'
 /// ```
+///
+/// Parsers without a type get a generated one in `#[parse_it(ast)]` mode.
 #[derive(Debug)]
 pub struct Parser {
     pub vis: syn::Visibility,
     pub name: syn::Ident,
-    pub ty: syn::Type,
+    pub ty: Option<syn::Type>,
     pub rules: Vec<Rule>,
+    pub iter: Option<IterConfig>,
+}
+
+/// `#[parse_it(iter(Item, sep = Atom))]`: generate a `parse_iter` method
+/// lazily parsing a sequence of `Item`s.
+#[derive(Debug)]
+pub struct IterConfig {
+    pub item: syn::Ident,
+    pub sep: Option<Atom>,
 }
 
 impl syn::parse::Parse for Parser {
     fn parse(input: syn::parse::ParseStream) -> syn::Result<Self> {
+        let attrs = input.call(Attribute::parse_outer)?;
+        let mut iter = None;
+        for attr in attrs {
+            if !attr.path().is_ident("parse_it") {
+                return Err(syn::Error::new_spanned(attr, "unknown attribute"));
+            }
+            attr.parse_nested_meta(|meta| {
+                if meta.path.is_ident("iter") {
+                    let content;
+                    syn::parenthesized!(content in meta.input);
+                    let item = content.parse::<syn::Ident>()?;
+                    let mut sep = None;
+                    if content.parse::<Option<Token![,]>>()?.is_some() && !content.is_empty() {
+                        let key = content.parse::<syn::Ident>()?;
+                        if key != "sep" {
+                            Err(syn::Error::new_spanned(key, "unknown attribute"))?
+                        }
+                        content.parse::<Token![=]>()?;
+                        sep = Some(content.parse::<Atom>()?);
+                        content.parse::<Option<Token![,]>>()?;
+                    }
+                    iter = Some(IterConfig { item, sep });
+                } else {
+                    Err(syn::Error::new_spanned(meta.path, "unknown attribute"))?
+                }
+                Ok(())
+            })?;
+        }
+
         let vis = input.parse::<syn::Visibility>()?;
         let name = input.parse::<syn::Ident>()?;
-        input.parse::<Token![->]>()?;
-        let ty = input.parse::<syn::Type>()?;
+        let ty = if input.peek(Token![->]) {
+            input.parse::<Token![->]>()?;
+            Some(input.parse::<syn::Type>()?)
+        } else {
+            None
+        };
 
         let content;
         syn::braced!(content in input);
@@ -202,28 +312,44 @@ impl syn::parse::Parse for Parser {
             name,
             ty,
             rules,
+            iter,
         })
     }
 }
 
 /// ```text
-/// Rule ::= Production '=>' Expr
+/// Rule ::= Production ('=>' Expr)?
 /// ```
+///
+/// Rules without an action, or whose action names a variant, belong to
+/// parsers with a generated type.
 #[derive(Debug)]
 pub struct Rule {
     pub production: Production,
-    pub action: syn::Expr,
+    pub action: Option<syn::Expr>,
 }
 
 impl syn::parse::Parse for Rule {
     fn parse(input: syn::parse::ParseStream) -> syn::Result<Self> {
         let production = input.parse::<Production>()?;
+        if !input.peek(Token![=>]) {
+            if !input.is_empty() {
+                input.parse::<Token![,]>()?;
+            }
+            return Ok(Rule {
+                production,
+                action: None,
+            });
+        }
         input.parse::<Token![=>]>()?;
         let action = input.parse::<syn::Expr>()?;
         if (requires_comma_to_be_match_arm(&action) && !input.is_empty()) || input.peek(Token![,]) {
             input.parse::<Token![,]>()?;
         }
-        Ok(Rule { production, action })
+        Ok(Rule {
+            production,
+            action: Some(action),
+        })
     }
 }
 
@@ -246,7 +372,11 @@ impl syn::parse::Parse for Production {
     fn parse(input: syn::parse::ParseStream) -> syn::Result<Self> {
         let first_part = input.parse::<Part>()?;
         let mut rest_parts = Vec::new();
-        while !input.peek(Token![=>]) && !input.peek(Token![|]) && !input.is_empty() {
+        while !input.peek(Token![=>])
+            && !input.peek(Token![|])
+            && !input.peek(Token![,])
+            && !input.is_empty()
+        {
             // Production ::= Part+
             rest_parts.push(input.parse::<Part>()?);
         }
@@ -350,12 +480,16 @@ impl syn::parse::Parse for Part {
 ///        | '[' Production ('|' Production)* ']'
 ///        | Terminal
 ///        | NonTerminal
-/// Terminal ::= Literal | Pat | '<' Type '>'
+/// Terminal ::= Literal 'i'? | Regex | Pat ('@' Ident)? | '<' Type '>'
+/// Regex ::= LitStr 're'
 /// ```
 #[derive(Debug)]
 pub enum Atom {
     Terminal(syn::Lit),
+    RegexTerminal(syn::LitStr),
     PatTerminal(syn::Pat),
+    /// `token @ span`
+    SpannedPatTerminal(syn::Pat, syn::Ident),
     TypePterminal(syn::Type),
     NonTerminal(syn::Ident),
     Sub(Box<Production>),
@@ -371,10 +505,20 @@ impl syn::parse::Parse for Atom {
     fn parse(input: syn::parse::ParseStream) -> syn::Result<Self> {
         let lookahead = input.lookahead1();
         let atom = if lookahead.peek(syn::token::Paren) {
-            // Atom ::= '(' Production ')'
-            let content;
-            syn::parenthesized!(content in input);
-            Atom::Sub(Box::new(content.parse()?))
+            let fork = input.fork();
+            match fork.call(syn::Pat::parse_single) {
+                Ok(pat @ syn::Pat::Tuple(_)) => {
+                    // Atom ::= Pat ('@' Ident)?
+                    input.advance_to(&fork);
+                    Atom::pat_terminal(pat, input)?
+                }
+                _ => {
+                    // Atom ::= '(' Production ')'
+                    let content;
+                    syn::parenthesized!(content in input);
+                    Atom::Sub(Box::new(content.parse()?))
+                }
+            }
         } else if lookahead.peek(syn::token::Bracket) {
             // Atom ::= '[' Production ('|' Production)* ']'
             let content;
@@ -387,8 +531,14 @@ impl syn::parse::Parse for Atom {
                 .ok_or_else(|| content.error("expected at least one choice"))?;
             Atom::Choice(Box::new(first_choice), choices.collect())
         } else if lookahead.peek(syn::Lit) {
-            // Atom ::= Terminal
-            Atom::Terminal(input.parse()?)
+            match input.parse()? {
+                syn::Lit::Str(lit) if lit.suffix() == "re" => {
+                    // Atom ::= Regex
+                    Atom::RegexTerminal(lit)
+                }
+                // Atom ::= Terminal
+                lit => Atom::Terminal(lit),
+            }
         } else if lookahead.peek(Token![<]) {
             // Atom ::= '<' Type '>'
             input.parse::<Token![<]>()?;
@@ -398,13 +548,13 @@ impl syn::parse::Parse for Atom {
         } else if lookahead.peek(syn::Ident) {
             let fork = input.fork();
             if let Ok(pat) = fork.call(syn::Pat::parse_single) {
-                if matches!(&pat, syn::Pat::Ident(_)) {
+                if matches!(&pat, syn::Pat::Ident(pat) if pat.subpat.is_none()) {
                     // Atom ::= NonTerminal
                     Atom::NonTerminal(input.parse()?)
                 } else {
-                    // Atom ::= PatTerminal
+                    // Atom ::= Pat ('@' Ident)?
                     input.advance_to(&fork);
-                    Atom::PatTerminal(pat)
+                    Atom::pat_terminal(pat, input)?
                 }
             } else {
                 Err(lookahead.error())?
@@ -417,26 +567,73 @@ impl syn::parse::Parse for Atom {
     }
 }
 
+impl Atom {
+    /// A pattern terminal, with the span of the token bound after an `@`.
+    ///
+    /// As in Rust patterns, `name @ span` parses as a binding whose
+    /// subpattern is `span`, so a trailing binding is split off it here.
+    fn pat_terminal(pat: syn::Pat, input: syn::parse::ParseStream) -> syn::Result<Self> {
+        fn split_span(pat: syn::Pat) -> (syn::Pat, Option<syn::Ident>) {
+            match pat {
+                syn::Pat::Ident(mut pat) => match pat.subpat.take() {
+                    Some((_, subpat)) => match *subpat {
+                        syn::Pat::Ident(span)
+                            if span.subpat.is_none()
+                                && span.by_ref.is_none()
+                                && span.mutability.is_none() =>
+                        {
+                            (syn::Pat::Ident(pat), Some(span.ident))
+                        }
+                        subpat => {
+                            let (subpat, span) = split_span(subpat);
+                            pat.subpat = Some((Default::default(), Box::new(subpat)));
+                            (syn::Pat::Ident(pat), span)
+                        }
+                    },
+                    None => (syn::Pat::Ident(pat), None),
+                },
+                pat => (pat, None),
+            }
+        }
+
+        let (pat, span) = split_span(pat);
+        let span = match span {
+            Some(span) => Some(span),
+            None if input.peek(Token![@]) => {
+                input.parse::<Token![@]>()?;
+                Some(input.parse()?)
+            }
+            None => None,
+        };
+        Ok(match span {
+            Some(span) => Atom::SpannedPatTerminal(pat, span),
+            None => Atom::PatTerminal(pat),
+        })
+    }
+}
+
 #[derive(Debug)]
 pub struct LexerConfig {
     pub crate_name: Option<syn::Path>,
     pub parse_macros: Rc<Vec<syn::Path>>,
     pub debug: bool,
+    /// use longest-match semantics instead of leftmost-first
+    pub longest_match: bool,
+    /// whether Unicode is enabled in regexes
+    pub unicode: bool,
+    /// whether regexes and keywords are case-insensitive
+    pub case_insensitive: bool,
 }
 
 impl Default for LexerConfig {
     fn default() -> Self {
         Self {
             crate_name: None,
-            parse_macros: Rc::new(vec![
-                syn::parse_quote! { print },
-                syn::parse_quote! { println },
-                syn::parse_quote! { eprint },
-                syn::parse_quote! { eprintln },
-                syn::parse_quote! { format },
-                syn::parse_quote! { dbg },
-            ]),
+            parse_macros: default_parse_macros(),
             debug: false,
+            longest_match: false,
+            unicode: true,
+            case_insensitive: false,
         }
     }
 }
@@ -447,7 +644,7 @@ pub struct LexerMod {
     pub mod_name: syn::Ident,
     pub items: Vec<syn::Item>,
     pub lexers: Vec<Lexer>,
-    pub config: ParserConfig,
+    pub config: LexerConfig,
 }
 
 impl LexerMod {
@@ -456,10 +653,36 @@ impl LexerMod {
         mod_name: syn::Ident,
         content: syn::parse::ParseStream,
     ) -> syn::Result<Self> {
+        let mut config = LexerConfig::default();
         let mut common_attrs = vec![];
         for attr in attrs {
             if attr.path().is_ident("parse_it") {
-                attr.parse_nested_meta(|_meta| todo!())?;
+                attr.parse_nested_meta(|meta| {
+                    if parse_common_option(
+                        &meta,
+                        &mut config.crate_name,
+                        &mut config.parse_macros,
+                        &mut config.debug,
+                    )? {
+                        return Ok(());
+                    }
+                    if meta.path.is_ident("longest_match") {
+                        let value = meta.value()?;
+                        let value = value.parse::<syn::LitBool>()?;
+                        config.longest_match = value.value;
+                    } else if meta.path.is_ident("unicode") {
+                        let value = meta.value()?;
+                        let value = value.parse::<syn::LitBool>()?;
+                        config.unicode = value.value;
+                    } else if meta.path.is_ident("case_insensitive") {
+                        let value = meta.value()?;
+                        let value = value.parse::<syn::LitBool>()?;
+                        config.case_insensitive = value.value;
+                    } else {
+                        Err(syn::Error::new_spanned(meta.path, "unknown attribute"))?
+                    }
+                    Ok(())
+                })?;
             } else {
                 common_attrs.push(attr);
             }
@@ -483,7 +706,7 @@ impl LexerMod {
             mod_name,
             items,
             lexers,
-            config: Default::default(),
+            config,
         })
     }
 }
@@ -504,15 +727,8 @@ impl syn::parse::Parse for Lexer {
     fn parse(input: syn::parse::ParseStream) -> syn::Result<Self> {
         let vis = input.parse()?;
         let name = input.parse()?;
-        let ty = if input.peek(Token![->]) {
-            input.parse::<Token![->]>()?;
-            Some(input.parse()?)
-        } else {
-            None
-        };
-
         let inputs = if input.peek(syn::token::Paren) {
-            // Lexer ::= Vis Name '(' Parameter* ')'
+            // Lexer ::= Vis Name '(' Parameter* ')' ...
             let content;
             syn::parenthesized!(content in input);
             Punctuated::<syn::PatType, Token![,]>::parse_terminated(&content)?
@@ -520,6 +736,14 @@ impl syn::parse::Parse for Lexer {
             Punctuated::new()
         };
 
+        let ty = if input.peek(Token![->]) {
+            // Lexer ::= ... '->' Type
+            input.parse::<Token![->]>()?;
+            Some(input.parse()?)
+        } else {
+            None
+        };
+
         let content;
         syn::braced!(content in input);
 
@@ -540,33 +764,108 @@ impl syn::parse::Parse for Lexer {
 }
 
 /// ```text
-/// LexerRule ::= LexerPattern '=>' Expr
+/// LexerRule ::= LexerPattern '=>' (Expr | '#' '[' 'trivia' ']') ('where' '{' Keyword* '}')?
 /// ```
 #[derive(Debug)]
 pub struct LexerRule {
     pub pattern: LexerPattern,
     pub action: syn::Expr,
+    /// whether the lexeme is recorded as trivia and skipped, instead of the
+    /// action
+    pub trivia: bool,
+    /// reserved words that take precedence over the action
+    pub keywords: Vec<Keyword>,
 }
 
 impl syn::parse::Parse for LexerRule {
     fn parse(input: syn::parse::ParseStream) -> syn::Result<Self> {
         let pattern = input.parse::<LexerPattern>()?;
         input.parse::<Token![=>]>()?;
+        if input.peek(Token![#]) {
+            // LexerRule ::= LexerPattern '=>' '#' '[' 'trivia' ']'
+            input.parse::<Token![#]>()?;
+            let content;
+            syn::bracketed!(content in input);
+            let ident = content.parse::<syn::Ident>()?;
+            if ident != "trivia" || !content.is_empty() {
+                return Err(syn::Error::new_spanned(ident, "expected `trivia`"));
+            }
+            if input.peek(Token![,]) {
+                input.parse::<Token![,]>()?;
+            }
+            return Ok(LexerRule {
+                pattern,
+                action: syn::parse_quote_spanned! { ident.span() => continue },
+                trivia: true,
+                keywords: vec![],
+            });
+        }
         let action = input.parse::<syn::Expr>()?;
+        if input.peek(Token![where]) {
+            // LexerRule ::= ... 'where' '{' Keyword* '}'
+            input.parse::<Token![where]>()?;
+            let content;
+            syn::braced!(content in input);
+            let mut keywords = vec![];
+            while !content.is_empty() {
+                keywords.push(content.parse::<Keyword>()?);
+            }
+            if input.peek(Token![,]) {
+                input.parse::<Token![,]>()?;
+            }
+            return Ok(LexerRule {
+                pattern,
+                action,
+                trivia: false,
+                keywords,
+            });
+        }
         if (requires_comma_to_be_match_arm(&action) && !input.is_empty()) || input.peek(Token![,]) {
             input.parse::<Token![,]>()?;
         }
-        Ok(LexerRule { pattern, action })
+        Ok(LexerRule {
+            pattern,
+            action,
+            trivia: false,
+            keywords: vec![],
+        })
     }
 }
 
 /// ```text
-/// LexerPattern ::= Regex | Name
+/// Keyword ::= String '=>' Expr
+/// ```
+#[derive(Debug)]
+pub struct Keyword {
+    pub word: syn::LitStr,
+    pub action: syn::Expr,
+}
+
+impl syn::parse::Parse for Keyword {
+    fn parse(input: syn::parse::ParseStream) -> syn::Result<Self> {
+        let word = input.parse::<syn::LitStr>()?;
+        input.parse::<Token![=>]>()?;
+        let action = input.parse::<syn::Expr>()?;
+        if (requires_comma_to_be_match_arm(&action) && !input.is_empty()) || input.peek(Token![,]) {
+            input.parse::<Token![,]>()?;
+        }
+        Ok(Keyword { word, action })
+    }
+}
+
+/// ```text
+/// LexerPattern ::= Regex 'i'? ('..' Regex 'i'?)? | Name ('(' Expr* ')')? | '_'
 /// ```
 #[derive(Debug)]
 pub enum LexerPattern {
     Regex(syn::LitStr),
-    Name(syn::Ident),
+    /// matches from an opening delimiter to the matching closing one,
+    /// allowing nesting
+    Nested(syn::LitStr, syn::LitStr),
+    /// includes the rules of another lexer, with arguments for its inputs
+    Name(syn::Ident, Punctuated<syn::Expr, Token![,]>),
+    /// matches a single character where no other rule matches
+    Error(Token![_]),
 }
 
 impl syn::parse::Parse for LexerPattern {
@@ -574,10 +873,26 @@ impl syn::parse::Parse for LexerPattern {
         let lookahead = input.lookahead1();
         if lookahead.peek(syn::Ident) {
             let ident = input.parse()?;
-            Ok(Self::Name(ident))
+            let args = if input.peek(syn::token::Paren) {
+                let content;
+                syn::parenthesized!(content in input);
+                Punctuated::parse_terminated(&content)?
+            } else {
+                Punctuated::new()
+            };
+            Ok(Self::Name(ident, args))
         } else if lookahead.peek(syn::LitStr) {
             let regex = input.parse()?;
-            Ok(Self::Regex(regex))
+            if input.peek(Token![..]) {
+                input.parse::<Token![..]>()?;
+                let close = input.parse()?;
+                Ok(Self::Nested(regex, close))
+            } else {
+                Ok(Self::Regex(regex))
+            }
+        } else if lookahead.peek(Token![_]) {
+            let underscore = input.parse()?;
+            Ok(Self::Error(underscore))
         } else {
             Err(lookahead.error())
         }
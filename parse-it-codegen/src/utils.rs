@@ -2,7 +2,11 @@ use std::rc::Rc;
 
 use proc_macro2::{Span, TokenStream};
 use quote::{format_ident, quote, ToTokens};
-use syn::{punctuated::Punctuated, visit_mut::VisitMut, Token};
+use regex_automata::{
+    dfa::{dense, StartKind},
+    MatchKind,
+};
+use syn::{punctuated::Punctuated, spanned::Spanned, visit_mut::VisitMut, Token};
 
 pub struct RewriteSelfVisitor {
     pub parse_macros: Rc<Vec<syn::Path>>,
@@ -10,6 +14,10 @@ pub struct RewriteSelfVisitor {
     pub self_ident: syn::Ident,
     /// whether `self` is referred
     pub referred_self: bool,
+    /// replace `state!()` with this ident
+    pub state_ident: syn::Ident,
+    /// whether `state!()` is referred
+    pub referred_state: bool,
 }
 
 impl RewriteSelfVisitor {
@@ -18,11 +26,26 @@ impl RewriteSelfVisitor {
             parse_macros,
             self_ident: format_ident!("r#__self", span = Span::call_site()),
             referred_self: false,
+            state_ident: format_ident!("r#__parser_state", span = Span::call_site()),
+            referred_state: false,
         }
     }
 }
 
 impl VisitMut for RewriteSelfVisitor {
+    fn visit_expr_mut(&mut self, i: &mut syn::Expr) {
+        if let syn::Expr::Macro(m) = i {
+            if m.mac.path.is_ident("state") && m.mac.tokens.is_empty() {
+                let mut ident = self.state_ident.clone();
+                ident.set_span(m.mac.path.span());
+                *i = syn::parse_quote! { #ident };
+                self.referred_state = true;
+                return;
+            }
+        }
+        syn::visit_mut::visit_expr_mut(self, i);
+    }
+
     fn visit_ident_mut(&mut self, i: &mut proc_macro2::Ident) {
         if i == "self" {
             let span = i.span();
@@ -62,3 +85,51 @@ impl VisitMut for RewriteSelfVisitor {
         }
     }
 }
+
+/// Compile the patterns of a lexer or a regex terminal into a sparse DFA, serialized in little and
+/// big endian.
+///
+/// Returns `None` if the patterns are not supported by DFAs, e.g. Unicode word
+/// boundaries, or the DFA is too large, in which case the generated code falls
+/// back to a regex compiled at runtime.
+pub fn compile_dfa(patterns: &[String], longest_match: bool) -> Option<(Vec<u8>, Vec<u8>)> {
+    const SIZE_LIMIT: usize = 1 << 22;
+
+    let match_kind = if longest_match {
+        MatchKind::All
+    } else {
+        MatchKind::LeftmostFirst
+    };
+    let dfa = dense::Builder::new()
+        .configure(
+            dense::Config::new()
+                .match_kind(match_kind)
+                .start_kind(StartKind::Anchored)
+                .dfa_size_limit(Some(SIZE_LIMIT))
+                .determinize_size_limit(Some(SIZE_LIMIT)),
+        )
+        .build_many(patterns)
+        .ok()?
+        .to_sparse()
+        .ok()?;
+    Some((dfa.to_bytes_little_endian(), dfa.to_bytes_big_endian()))
+}
+
+/// Define a `DFA` static holding a DFA compiled by [`compile_dfa`].
+///
+/// The DFA is deserialized on first use, with validation. This is linear in
+/// its size, but involves no regex compilation and no unsafe code.
+pub fn dfa_static(crate_name: &TokenStream, (little, big): (Vec<u8>, Vec<u8>)) -> TokenStream {
+    let little = syn::LitByteStr::new(&little, Span::call_site());
+    let big = syn::LitByteStr::new(&big, Span::call_site());
+    quote! {
+        static DFA: ::std::sync::LazyLock<#crate_name::lexer::Dfa> =
+            ::std::sync::LazyLock::new(|| {
+                #[cfg(target_endian = "little")]
+                let bytes = #little;
+                #[cfg(target_endian = "big")]
+                let bytes = #big;
+                #crate_name::lexer::load_dfa(bytes)
+            });
+    }
+}
@@ -32,3 +32,12 @@ pub fn parse_it(input: proc_macro::TokenStream) -> proc_macro::TokenStream {
     }
     result
 }
+
+#[proc_macro_derive(TryConvert, attributes(parse_it))]
+pub fn derive_try_convert(input: proc_macro::TokenStream) -> proc_macro::TokenStream {
+    let input = syn::parse_macro_input!(input as syn::DeriveInput);
+    match parse_it_codegen::derive::try_convert(input) {
+        Ok(expanded) => expanded.into(),
+        Err(msg) => msg.into(),
+    }
+}
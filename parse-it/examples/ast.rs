@@ -0,0 +1,281 @@
+use parse_it::ParseIt;
+
+parse_it::parse_it! {
+    #[parser]
+    #[parse_it(ast)]
+    mod parse {
+        type Lexer = parse_it::CharLexer;
+
+        pub Expr {
+            lhs:Expr '+' rhs:Term => Add,
+            lhs:Expr '-' rhs:Term => Sub,
+            Term,
+        }
+
+        pub Term {
+            Num,
+            Call,
+            '(' expr:Expr ')' => Paren,
+        }
+
+        pub Num {
+            digits:r"[0-9]+"re,
+        }
+
+        pub Call {
+            name:ASCIIName '(' args:Expr* ')',
+        }
+
+        ASCIIName -> String {
+            name:r"[a-z]+"re => name.to_string(),
+        }
+    }
+
+    #[lexer]
+    mod lex {
+        use parse_it::lexer::TryConvert;
+
+        #[derive(Debug, Clone, Copy, TryConvert)]
+        pub enum Token {
+            Num(i64),
+            Plus,
+        }
+
+        pub Initial -> Token {
+            r"\s+" => #[trivia],
+            r"[0-9]+" => Token::Num(self.parse().unwrap()),
+            r"\+" => Token::Plus,
+        }
+    }
+
+    #[parser]
+    #[parse_it(ast = "syntax")]
+    mod spaced {
+        type Lexer = super::lex::Initial;
+
+        pub Sum {
+            lhs:Sum "+" rhs:Operand => Add,
+            Operand,
+        }
+
+        pub Operand {
+            value:<i64>,
+        }
+    }
+
+    #[parser]
+    #[parse_it(visit)]
+    mod typed {
+        type Lexer = parse_it::CharLexer;
+
+        #[derive(Debug, Clone, PartialEq)]
+        pub enum Value {
+            Num(i64),
+            List(Vec<Value>),
+            Tagged { tag: Tag, value: Box<Value> },
+        }
+
+        #[derive(Debug, Clone, PartialEq)]
+        pub struct Tag(pub String);
+
+        pub Item -> Value {
+            n:Int => Value::Num(n),
+            n:Negative => Value::Num(n),
+            '[' vs:(Item ',')* v:Item ']' => {
+                Value::List(vs.into_iter().chain(std::iter::once(v)).collect())
+            }
+            tag:Label ':' value:Item => Value::Tagged { tag, value: Box::new(value) },
+        }
+
+        Int -> i64 {
+            digits:r"[0-9]+"re => digits.parse().unwrap(),
+        }
+
+        Negative -> i64 {
+            '-' n:Int => -n,
+        }
+
+        Label -> Tag {
+            name:r"[a-z]+"re => Tag(name.to_owned()),
+        }
+    }
+}
+
+use parse::ast::{
+    fold::{self, Fold},
+    visit::{self, Visit},
+    visit_mut::{self, VisitMut},
+    Call, Expr, Num, Term,
+};
+
+fn eval(expr: &Expr) -> i64 {
+    match expr {
+        Expr::Add { lhs, rhs, .. } => eval(lhs) + eval_term(rhs),
+        Expr::Sub { lhs, rhs, .. } => eval(lhs) - eval_term(rhs),
+        Expr::Term(term) => eval_term(term),
+    }
+}
+
+fn eval_term(term: &Term) -> i64 {
+    match term {
+        Term::Num(num) => num.digits.parse().unwrap(),
+        Term::Call(call) if call.name == "neg" => -eval(&call.args[0]),
+        Term::Call(call) => panic!("unknown function `{}`", call.name),
+        Term::Paren { expr, .. } => eval(expr),
+    }
+}
+
+/// Collects the numbers, in order.
+struct Nums(Vec<i64>);
+
+impl<'ast> Visit<'ast> for Nums {
+    fn visit_num(&mut self, num: &'ast Num) {
+        self.0.push(num.digits.parse().unwrap());
+    }
+}
+
+/// Counts the calls, including nested ones.
+struct Calls(usize);
+
+impl<'ast> Visit<'ast> for Calls {
+    fn visit_call(&mut self, call: &'ast Call) {
+        self.0 += 1;
+        visit::visit_call(self, call);
+    }
+}
+
+/// Collects the names of the called functions.
+struct Names(Vec<String>);
+
+impl<'ast> Visit<'ast> for Names {
+    fn visit_ascii_name(&mut self, name: &'ast String) {
+        self.0.push(name.clone());
+    }
+}
+
+/// Collects the tags and the numbers of values, in order.
+#[derive(Default)]
+struct Leaves(Vec<String>, Vec<i64>);
+
+impl<'ast> typed::visit::Visit<'ast> for Leaves {
+    fn visit_label(&mut self, tag: &'ast typed::Tag) {
+        self.0.push(tag.0.clone());
+    }
+
+    fn visit_int(&mut self, n: &'ast i64) {
+        self.1.push(*n);
+    }
+}
+
+/// Adds one to every number.
+struct Inc;
+
+impl typed::fold::Fold for Inc {
+    fn fold_int(&mut self, n: i64) -> i64 {
+        n + 1
+    }
+}
+
+/// Doubles every number.
+struct Double;
+
+impl VisitMut for Double {
+    fn visit_num_mut(&mut self, num: &mut Num) {
+        num.digits = (num.digits.parse::<i64>().unwrap() * 2).to_string();
+    }
+}
+
+/// Inlines `neg(x)` as `0-(x)`.
+struct InlineNeg;
+
+impl Fold for InlineNeg {
+    fn fold_term(&mut self, term: Term) -> Term {
+        match fold::fold_term(self, term) {
+            Term::Call(call) if call.name == "neg" => {
+                let Call { mut args, span, .. } = *call;
+                let zero = Num {
+                    digits: "0".to_owned(),
+                    span,
+                };
+                Term::Paren {
+                    expr: Box::new(Expr::Sub {
+                        lhs: Box::new(Expr::Term(Box::new(Term::Num(zero)))),
+                        rhs: Box::new(Term::Paren {
+                            expr: Box::new(args.remove(0)),
+                            span,
+                        }),
+                        span,
+                    }),
+                    span,
+                }
+            }
+            term => term,
+        }
+    }
+}
+
+fn main() {
+    let parser = parse::Expr::default();
+    let ast = parser.parse("1+(20-3)-neg(4)").unwrap();
+    assert_eq!(eval(&ast), 22);
+
+    let Expr::Sub { lhs, rhs, span } = &ast else {
+        panic!("expected a subtraction");
+    };
+    assert_eq!((span.start, span.end), (0, 15));
+    assert!(matches!(**lhs, Expr::Add { .. }));
+    let Term::Call(call) = &**rhs else {
+        panic!("expected a call");
+    };
+    assert_eq!((call.span.start, call.span.end), (9, 15));
+
+    // the spans are those of the tokens, without the trivia around them
+    let sum = spaced::Sum::default().parse("  1 +  20 ").unwrap();
+    let spaced::syntax::Sum::Add { lhs, rhs, span } = sum else {
+        panic!("expected an addition");
+    };
+    assert_eq!((span.start, span.end), (2, 9));
+    let spaced::syntax::Sum::Operand(lhs) = *lhs else {
+        panic!("expected an operand");
+    };
+    assert_eq!((lhs.span.start, lhs.span.end), (2, 3));
+    assert_eq!((rhs.span.start, rhs.span.end), (7, 9));
+    assert_eq!((lhs.value, rhs.value), (1, 20));
+
+    let mut nums = Nums(vec![]);
+    nums.visit_expr(&ast);
+    assert_eq!(nums.0, [1, 20, 3, 4]);
+
+    let mut doubled = ast.clone();
+    Double.visit_expr_mut(&mut doubled);
+    assert_eq!(eval(&doubled), 44);
+    visit_mut::visit_expr_mut(&mut Double, &mut doubled);
+    assert_eq!(eval(&doubled), 88);
+
+    let inlined = InlineNeg.fold_expr(ast.clone());
+    assert_eq!(eval(&inlined), 22);
+    let mut calls = Calls(0);
+    calls.visit_expr(&ast);
+    assert_eq!(calls.0, 1);
+    calls.visit_expr(&inlined);
+    assert_eq!(calls.0, 1);
+    let mut names = Names(vec![]);
+    names.visit_expr(&ast);
+    assert_eq!(names.0, ["neg"]);
+    names.visit_expr(&inlined);
+    assert_eq!(names.0, ["neg"]);
+
+    // the visitors of types of the parser module walk their fields, and
+    // parsers of the same type share the methods of the first of them
+    let value = typed::Item::default().parse("[1,a:[-2,b:3],4]").unwrap();
+    let mut leaves = Leaves::default();
+    typed::visit::Visit::visit_item(&mut leaves, &value);
+    assert_eq!(leaves.0, ["a", "b"]);
+    assert_eq!(leaves.1, [1, -2, 3, 4]);
+    let value = typed::fold::Fold::fold_item(&mut Inc, value);
+    let mut leaves = Leaves::default();
+    typed::visit::Visit::visit_item(&mut leaves, &value);
+    assert_eq!(leaves.1, [2, -1, 4, 5]);
+
+    println!("{ast:#?}");
+}
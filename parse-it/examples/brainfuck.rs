@@ -1,7 +1,5 @@
 use std::io::{BufRead, BufReader, Bytes, Read};
 
-use parse_it::ParseIt;
-
 #[derive(Debug, Clone)]
 pub enum Instr {
     Left,
@@ -20,6 +18,7 @@ parse_it::parse_it! {
 
         type Lexer = parse_it::CharLexer;
 
+        #[parse_it(iter(Primitive))]
         pub Brainfuck -> Vec<Instr> {
             Primitive* => self,
         }
@@ -40,13 +39,15 @@ fn main() {
     let parser = parse::Brainfuck::default();
     let src = "--[>--->->->++>-<<<<<-------]>--.>---------.>--..+++.>----.>+++++++++.<<.+++.------.<-.>>+.";
 
-    match parser.parse(src) {
-        Ok(ast) => {
-            let mut stdin = BufReader::new(std::io::stdin().lock()).bytes();
-            execute(&ast, &mut 0, &mut [0; TAPE_LEN], &mut stdin)
+    let mut stdin = BufReader::new(std::io::stdin().lock()).bytes();
+    let (ptr, tape) = (&mut 0, &mut [0; TAPE_LEN]);
+    // run each instruction as soon as it is parsed
+    for instr in parser.parse_iter(src) {
+        match instr {
+            Ok(instr) => execute(&[instr], ptr, tape, &mut stdin),
+            Err(err) => return println!("{err:?}"),
         }
-        Err(err) => println!("{err:?}"),
-    };
+    }
 }
 
 const TAPE_LEN: usize = 10_000;
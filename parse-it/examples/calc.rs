@@ -5,12 +5,8 @@ parse_it::parse_it! {
     mod parse {
         type Lexer = parse_it::CharLexer;
 
-        Digit -> char {
-            @['0' | '1' | '2' | '3' | '4' | '5' | '6' | '7' | '8' | '9'] => self
-        }
-
         Num -> i32 {
-            digits:Digit+ => digits.into_iter().collect::<String>().parse::<i32>().unwrap(),
+            digits:r"[0-9]+"re => digits.parse::<i32>().unwrap(),
         }
 
         pub Expr -> i32 {
@@ -0,0 +1,135 @@
+use parse_it::{lexer::Span, ParserState};
+
+parse_it::parse_it! {
+    #[lexer]
+    mod lex {
+        #[derive(Debug, Clone, PartialEq)]
+        pub enum Token {
+            Num(i32),
+            Punct(char),
+        }
+
+        pub Initial -> Token {
+            r"\s+" => continue,
+            r"[0-9]+" => Token::Num(self.parse().unwrap()),
+            r"[-+*/()]" => Token::Punct(self.chars().next().unwrap()),
+        }
+    }
+
+    #[parser]
+    #[parse_it(cst)]
+    mod parse {
+        use super::lex::Token;
+
+        type Lexer = super::lex::Initial;
+
+        pub Expr -> i32 {
+            lhs:Expr Token::Punct('+') rhs:Term => lhs + rhs,
+            lhs:Expr Token::Punct('-') rhs:Term => lhs - rhs,
+            Term => self,
+        }
+
+        Term -> i32 {
+            Token::Num(n) => n,
+            Token::Punct('(') expr:Expr Token::Punct(')') => expr,
+        }
+    }
+
+    #[parser]
+    #[parse_it(cst)]
+    mod fields {
+        type Lexer = parse_it::ByteLexer;
+
+        pub Fields -> usize {
+            fields:Field* => fields.len(),
+        }
+
+        Field -> Vec<u8> {
+            field:(!b',' <u8>)+ b','? => field,
+        }
+    }
+
+    #[parser]
+    #[parse_it(cst)]
+    mod letters {
+        type Lexer = parse_it::lexer::SliceLexer<char>;
+
+        pub Word -> String {
+            letters:Letter+ => letters.into_iter().collect(),
+        }
+
+        Letter -> char {
+            c @ 'a'..='z' => c,
+        }
+    }
+}
+
+fn main() {
+    let parser = parse::Expr::default();
+    let input = " 1 + (20 - 3) ";
+
+    let (value, root) = parser.parse_cst(input).unwrap();
+    assert_eq!(value, 18);
+
+    // the tree keeps all the source text, trivia included
+    assert_eq!(root.to_string(), input);
+    let text = root.tokens().map(|t| t.to_string()).collect::<String>();
+    assert_eq!(text, input);
+    let tokens = root
+        .tokens()
+        .filter(|t| !t.is_trivia())
+        .map(|t| t.to_string())
+        .collect::<Vec<_>>();
+    assert_eq!(tokens, ["1", "+", "(", "20", "-", "3", ")"]);
+
+    let kinds = root.descendants().map(|n| n.kind()).collect::<Vec<_>>();
+    assert_eq!(
+        kinds,
+        ["Expr", "Expr", "Term", "Term", "Expr", "Expr", "Term", "Term"]
+    );
+
+    // navigate from the innermost number back to the root
+    let three = root.descendants().last().unwrap();
+    assert_eq!(three.text(), Some("3"));
+    let inner = three.parent().unwrap();
+    assert_eq!(inner.text(), Some("20 - 3"));
+    assert_eq!(inner.first_child().unwrap().next_sibling().unwrap(), three);
+    assert_eq!(three.ancestors().last().unwrap(), root);
+
+    print_tree(&root, 0);
+
+    // the text of a node is cut from the source bytes at its span
+    let parser = fields::Fields::default();
+    let state = ParserState::from_bytes(b"caf\xc3\xa9,\xff\xfe,ok");
+    let (count, root) = parser.parse_cst(state).unwrap();
+    assert_eq!(count, 3);
+    let fields = root.children().collect::<Vec<_>>();
+    assert_eq!(fields[0].text(), Some("caf\u{e9},"));
+    assert_eq!(fields[1].text(), None);
+    assert_eq!(fields[1].bytes(), b"\xff\xfe,");
+    assert_eq!(fields[2].text(), Some("ok"));
+
+    // pre-lexed tokens have no source text
+    let parser = letters::Word::default();
+    let tokens = [
+        ('o', Span { start: 0, end: 1 }),
+        ('k', Span { start: 2, end: 3 }),
+    ];
+    let (word, root) = parser.parse_cst(ParserState::from_tokens(&tokens)).unwrap();
+    assert_eq!(word, "ok");
+    assert_eq!(root.span(), Span { start: 0, end: 3 });
+    assert_eq!(root.text(), Some(""));
+}
+
+fn print_tree(node: &parse_it::cst::SyntaxNode, depth: usize) {
+    println!(
+        "{:indent$}{:?} {:?}",
+        "",
+        node,
+        node.to_string(),
+        indent = depth * 2
+    );
+    for child in node.children() {
+        print_tree(&child, depth + 1);
+    }
+}
@@ -0,0 +1,97 @@
+use std::num::NonZeroI64;
+
+use parse_it::ParseIt;
+
+#[derive(Clone, Copy, Default, PartialEq, Eq)]
+pub enum Dialect {
+    #[default]
+    Standard,
+    /// integers with a leading zero are octal
+    C,
+}
+
+parse_it::parse_it! {
+    #[lexer]
+    mod lex {
+        use std::num::NonZeroI64;
+
+        use super::Dialect;
+
+        #[derive(Debug)]
+        pub enum Token {
+            Number(i64),
+            #[token("+")]
+            Plus,
+        }
+
+        pub Initial(dialect: Dialect) -> Token {
+            r"\s+" => continue,
+            Number(dialect) => Token::Number(self),
+            r"\+" => Token::Plus,
+        }
+
+        // a lexer whose input has no default is constructed with its fields
+        pub Capped(cap: NonZeroI64) -> Token {
+            r"\s+" => continue,
+            Number(Dialect::Standard) => Token::Number(self.min(cap.get())),
+            r"\+" => Token::Plus,
+        }
+
+        Number(dialect: Dialect) -> i64 {
+            r"0[0-7]+" => match dialect {
+                Dialect::Standard => self.parse().unwrap(),
+                Dialect::C => i64::from_str_radix(&self[1..], 8).unwrap(),
+            },
+            r"[0-9]+" => self.parse().unwrap(),
+        }
+    }
+
+    #[parser]
+    mod parse {
+        use super::lex::Token;
+
+        type Lexer = super::lex::Initial;
+
+        pub Sum -> i64 {
+            lhs:Sum '+' Token::Number(rhs) => lhs + rhs,
+            Token::Number(n) => n,
+        }
+    }
+
+    #[parser]
+    mod capped {
+        use super::lex::Token;
+
+        type Lexer = super::lex::Capped;
+
+        pub Sum -> i64 {
+            lhs:Sum '+' Token::Number(rhs) => lhs + rhs,
+            Token::Number(n) => n,
+        }
+    }
+}
+
+fn main() {
+    let src = "010 + 7";
+
+    let parser = parse::Sum::default();
+    let result = parser.parse(src).unwrap();
+    println!("{result}");
+    assert_eq!(result, 17);
+
+    let parser = parse::Sum::default();
+    let lexer = lex::Initial {
+        dialect: Dialect::C,
+    };
+    let result = parser.parse_with_lexer(src, lexer).unwrap();
+    println!("{result}");
+    assert_eq!(result, 15);
+
+    let parser = capped::Sum::default();
+    let lexer = lex::Capped {
+        cap: NonZeroI64::new(9).unwrap(),
+    };
+    let result = parser.parse_with_lexer(src, lexer).unwrap();
+    println!("{result}");
+    assert_eq!(result, 16);
+}
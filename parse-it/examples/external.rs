@@ -0,0 +1,95 @@
+use std::ops::Range;
+
+use parse_it::{
+    lexer::{IterLexer, LexErrorKind},
+    ErrorKind, ParseIt,
+};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Token {
+    Number(f64),
+    Plus,
+    Star,
+    LParen,
+    RParen,
+}
+
+/// A hand-written lexer producing tokens and their byte ranges, in the style
+/// of `logos::SpannedIter`.
+pub struct Tokens<'s> {
+    src: &'s str,
+    pos: usize,
+}
+
+impl Iterator for Tokens<'_> {
+    type Item = (Token, Range<usize>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let rest = &self.src[self.pos..];
+        let start = self.pos + (rest.len() - rest.trim_start().len());
+        let rest = &self.src[start..];
+        let len = match rest.chars().next()? {
+            '0'..='9' => rest
+                .find(|c: char| !c.is_ascii_digit() && c != '.')
+                .unwrap_or(rest.len()),
+            _ => 1,
+        };
+        let token = match &rest[..len] {
+            "+" => Token::Plus,
+            "*" => Token::Star,
+            "(" => Token::LParen,
+            ")" => Token::RParen,
+            number => Token::Number(number.parse().ok()?),
+        };
+        self.pos = start + len;
+        Some((token, start..self.pos))
+    }
+}
+
+parse_it::parse_it! {
+    #[parser]
+    mod parse {
+        use super::Token;
+
+        type Lexer = parse_it::lexer::IterLexer<Token>;
+
+        pub Expr -> f64 {
+            lhs:Expr Token::Plus rhs:Term => lhs + rhs,
+            Term => self,
+        }
+
+        Term -> f64 {
+            lhs:Term Token::Star rhs:Factor => lhs * rhs,
+            Factor => self,
+        }
+
+        Factor -> f64 {
+            Token::Number(n) => n,
+            Token::LParen e:Expr Token::RParen => e,
+        }
+    }
+}
+
+fn main() {
+    let parser = parse::Expr::default();
+    let tokens = Tokens {
+        src: "1.5 * (2 + 2) + 4",
+        pos: 0,
+    };
+    let result = parser.parse_token_iter(tokens).unwrap();
+    println!("{result}");
+    assert_eq!(result, 10.0);
+
+    // the tokens may borrow a source text read at runtime
+    let src = ["2", "*", "(1 + 3)"].join(" ");
+    let parser = parse::Expr::default();
+    let tokens = Tokens { src: &src, pos: 0 };
+    assert_eq!(parser.parse_token_iter(tokens).unwrap(), 8.0);
+
+    // the tokens come from the lexer, not from the source text
+    let parser = parse::Expr::default();
+    let (lexer, _) = IterLexer::from_tokens(Tokens { src: "1", pos: 0 });
+    let error = parser.parse_with_lexer("1", lexer).unwrap_err();
+    println!("{error:?}");
+    assert_eq!(error.kind, ErrorKind::Lexical(LexErrorKind::MissingToken));
+}
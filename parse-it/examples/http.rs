@@ -0,0 +1,54 @@
+use parse_it::ParseIt;
+
+#[derive(Debug, Clone)]
+pub struct Request {
+    pub method: Vec<u8>,
+    pub target: Vec<u8>,
+    pub headers: Vec<(Vec<u8>, Vec<u8>)>,
+    pub body: Vec<u8>,
+}
+
+parse_it::parse_it! {
+    #[parser]
+    mod parse {
+        use super::Request;
+
+        type Lexer = parse_it::ByteLexer;
+
+        pub Message -> Request {
+            method:Word b' ' target:Word b" HTTP/1.1\r\n" headers:Header* b"\r\n" body:<u8>* => {
+                Request { method, target, headers, body }
+            }
+        }
+
+        Header -> (Vec<u8>, Vec<u8>) {
+            name:Word b':' b' '* value:Text b"\r\n" => (name, value),
+        }
+
+        Word -> Vec<u8> {
+            word:(!b' ' !b':' !b'\r' <u8>)+ => word,
+        }
+
+        Text -> Vec<u8> {
+            text:(!b'\r' <u8>)* => text,
+        }
+    }
+}
+
+fn main() {
+    let parser = parse::Message::default();
+    let src =
+        b"POST /upload HTTP/1.1\r\nHost: example.com\r\nContent-Length: 4\r\n\r\n\x89\xff\x00\xfe";
+    let request = parser.parse_bytes(src).unwrap();
+    println!("{request:?}");
+    assert_eq!(request.method, b"POST");
+    assert_eq!(request.target, b"/upload");
+    assert_eq!(
+        request.headers[1],
+        (b"Content-Length".to_vec(), b"4".to_vec())
+    );
+    assert_eq!(request.body, [0x89, 0xff, 0x00, 0xfe]);
+
+    let parser = parse::Message::default();
+    assert!(parser.parse_bytes(b"GET / HTTP/1.0\r\n\r\n").is_err());
+}
@@ -0,0 +1,110 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use parse_it::{lexer::Edit, ParseIt, ParserState};
+
+static ITEMS_PARSED: AtomicUsize = AtomicUsize::new(0);
+
+parse_it::parse_it! {
+    #[parser]
+    mod parse {
+        use super::ITEMS_PARSED;
+        use std::sync::atomic::Ordering;
+
+        type Lexer = parse_it::CharLexer;
+
+        pub Items -> Vec<u64> {
+            items:Item* => items,
+        }
+
+        Item -> u64 {
+            n:r"[0-9]+"re ',' => {
+                ITEMS_PARSED.fetch_add(1, Ordering::Relaxed);
+                n.parse().unwrap()
+            }
+        }
+
+        pub Measure -> (String, String) {
+            n:Number unit:<char>* => (n, unit.into_iter().collect()),
+        }
+
+        // word boundaries are not supported by DFAs, so the regex runs as is
+        Number -> String {
+            n:r"[0-9]+(?:\.[0-9]+)?\b"re => n.to_string(),
+        }
+
+        pub Letters -> Vec<(char, usize)> {
+            letters:Letter* => letters,
+        }
+
+        Letter -> (char, usize) {
+            c @ 'a'..='z' @ span => (c, span.start),
+        }
+    }
+}
+
+fn parsed() -> usize {
+    ITEMS_PARSED.swap(0, Ordering::Relaxed)
+}
+
+fn main() {
+    let parser = parse::Items::default();
+    let text = "1,22,333,4444,";
+    let mut state = ParserState::new(text);
+    assert_eq!(parser.parse_stream(&mut state).unwrap(), [1, 22, 333, 4444]);
+    assert_eq!(parsed(), 4);
+
+    // only the items around the edit are parsed again
+    let text = "1,25,333,4444,";
+    let mut state = state.edit(text, &Edit::new(2..4, "25"));
+    assert_eq!(parser.parse_stream(&mut state).unwrap(), [1, 25, 333, 4444]);
+    assert_eq!(parsed(), 2);
+
+    // items after the edit are moved along
+    let text = "1,25,6,333,4444,";
+    let mut state = state.edit(text, &Edit::new(5..5, "6,"));
+    assert_eq!(
+        parser.parse_stream(&mut state).unwrap(),
+        [1, 25, 6, 333, 4444]
+    );
+    assert_eq!(parsed(), 3);
+
+    let text = "1,25,6333,4444,";
+    let mut state = state.edit(text, &Edit::new(6..7, ""));
+    let items = parser.parse_stream(&mut state).unwrap();
+    println!("{items:?}");
+    assert_eq!(items, [1, 25, 6333, 4444]);
+
+    // a fresh state starts over
+    assert_eq!(parser.parse("7,").unwrap(), [7]);
+
+    // the memos are of the last text parsed, which the edited state is not
+    let mut first = ParserState::new("1,2,3,");
+    assert_eq!(parser.parse_stream(&mut first).unwrap(), [1, 2, 3]);
+    assert_eq!(parser.parse("7,8,9,").unwrap(), [7, 8, 9]);
+    parsed();
+    let mut state = first.edit("1,2,5,", &Edit::new(4..5, "5"));
+    assert_eq!(parser.parse_stream(&mut state).unwrap(), [1, 2, 5]);
+    assert_eq!(parsed(), 3);
+
+    // the regex looked past the number for a fraction
+    let parser = parse::Measure::default();
+    let mut state = ParserState::new("1.x");
+    let measure = parser.parse_stream(&mut state).unwrap();
+    assert_eq!(measure, ("1".to_string(), ".x".to_string()));
+    let mut state = state.edit("1.5", &Edit::new(2..3, "5"));
+    let measure = parser.parse_stream(&mut state).unwrap();
+    assert_eq!(measure, ("1.5".to_string(), String::new()));
+
+    // values holding positions are parsed again after an edit before them
+    let parser = parse::Letters::default();
+    let mut state = ParserState::new("ab");
+    assert_eq!(
+        parser.parse_stream(&mut state).unwrap(),
+        [('a', 0), ('b', 1)]
+    );
+    let mut state = state.edit("xab", &Edit::new(0..0, "x"));
+    assert_eq!(
+        parser.parse_stream(&mut state).unwrap(),
+        [('x', 0), ('a', 1), ('b', 2)]
+    );
+}
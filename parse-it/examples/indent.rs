@@ -0,0 +1,71 @@
+use parse_it::ParseIt;
+
+parse_it::parse_it! {
+    #[lexer]
+    mod lex {
+        use parse_it::lexer::Layout;
+
+        #[derive(Debug)]
+        pub enum Token<'a> {
+            Name(&'a str),
+            #[token(":")]
+            Colon,
+            Newline,
+            Indent,
+            Dedent,
+        }
+
+        pub Initial -> Token<'lex> {
+            r"(\n[ \t]*(#[^\n]*)?)+|\z" => match layout!(4) {
+                Layout::Newline => Token::Newline,
+                Layout::Indent => Token::Indent,
+                Layout::Dedent => Token::Dedent,
+            },
+            r"[ \t]+" => continue,
+            r"\w+" => Token::Name(self),
+            ":" => Token::Colon,
+        }
+    }
+
+    #[parser]
+    mod parse {
+        use super::lex::Token;
+
+        type Lexer = super::lex::Initial;
+
+        pub Items -> String {
+            items:Item+ => items.join(" "),
+        }
+
+        Item -> String {
+            Token::Name(name) ':' Token::Newline Token::Indent items:Items Token::Dedent => {
+                format!("{name}({items})")
+            }
+            Token::Name(name) Token::Newline => name.to_string(),
+        }
+    }
+}
+
+fn main() {
+    let parser = parse::Items::default();
+    let src = "fruits:
+    apple
+    citrus:
+        lemon
+        # sour
+\t\torange
+    pear
+vegetables:
+  carrot";
+    let result = parser.parse(src).unwrap();
+    println!("{result}");
+    assert_eq!(
+        result,
+        "fruits(apple citrus(lemon orange) pear) vegetables(carrot)"
+    );
+
+    let parser = parse::Items::default();
+    let error = parser.parse("fruits:\n    apple\n  pear\n").unwrap_err();
+    println!("{error:?}");
+    assert!(error.is_lexical());
+}
@@ -15,12 +15,30 @@ pub enum JsonValue {
 parse_it::parse_it! {
     #[lexer]
     mod lex {
-        #[derive(Debug)]
+        use parse_it::lexer::TryConvert;
+
+        #[derive(Debug, TryConvert)]
         pub enum Token {
             Number(f64),
             String(String),
-            Keyword,
-            Punct,
+            #[token("true")]
+            True,
+            #[token("false")]
+            False,
+            #[token("null")]
+            Null,
+            #[token("[")]
+            LBracket,
+            #[token("]")]
+            RBracket,
+            #[token("{")]
+            LBrace,
+            #[token("}")]
+            RBrace,
+            #[token(",")]
+            Comma,
+            #[token(":")]
+            Colon,
         }
 
         pub Initial -> Token {
@@ -31,15 +49,15 @@ parse_it::parse_it! {
                 while lex!(StringLit(&mut buf)).is_some() {}
                 Token::String(buf)
             },
-            "true" => Token::Keyword,
-            "false" => Token::Keyword,
-            "null" => Token::Keyword,
-            r"\[" => Token::Punct,
-            r"\]" => Token::Punct,
-            r"\{" => Token::Punct,
-            r"\}" => Token::Punct,
-            r"," => Token::Punct,
-            r":" => Token::Punct,
+            "true" => Token::True,
+            "false" => Token::False,
+            "null" => Token::Null,
+            r"\[" => Token::LBracket,
+            r"\]" => Token::RBracket,
+            r"\{" => Token::LBrace,
+            r"\}" => Token::RBrace,
+            r"," => Token::Comma,
+            r":" => Token::Colon,
         }
 
         Number -> f64 {
@@ -56,6 +74,9 @@ parse_it::parse_it! {
             r"\\b" => buf.push('\x08'),
             r"\\f" => buf.push('\x0C'),
             r"\\r" => buf.push('\r'),
+            r"\\u(?<hex>[0-9a-fA-F]{4})" => {
+                buf.push(char::from_u32(u32::from_str_radix(hex, 16).unwrap()).unwrap_or('\u{FFFD}'))
+            }
             r#"[^\"\\]"# => buf.push_str(self),
         }
     }
@@ -64,7 +85,6 @@ parse_it::parse_it! {
     mod parse {
         use std::collections::HashMap;
         use super::JsonValue;
-        use super::lex::Token;
 
         type Lexer = super::lex::Initial;
 
@@ -85,15 +105,15 @@ parse_it::parse_it! {
         }
 
         Key -> String {
-            Token::String(buf) => buf
+            <String> => self
         }
 
         pub Value -> JsonValue {
-            Token::Number(i) => JsonValue::Number(i),
-            Token::String(buf) => JsonValue::String(buf),
-            &Token::Keyword "true" => JsonValue::Boolean(true),
-            &Token::Keyword "false" => JsonValue::Boolean(false),
-            &Token::Keyword "null" => JsonValue::Null,
+            n:<f64> => JsonValue::Number(n),
+            s:<String> => JsonValue::String(s),
+            "true" => JsonValue::Boolean(true),
+            "false" => JsonValue::Boolean(false),
+            "null" => JsonValue::Null,
             Object => self,
             Array => self,
         }
@@ -101,7 +121,7 @@ parse_it::parse_it! {
 }
 
 fn main() {
-    let input = r#"{"name": "Alice", "age": 30, "is_student": false, "courses": ["Math", "Science"], "address": null}"#;
+    let input = r#"{"name": "Alice \u00c9mile", "age": 30, "is_student": false, "courses": ["Math", "Science"], "address": null}"#;
 
     let parser = parse::Value::default();
     let json = parser.parse(input).unwrap();
@@ -2,24 +2,37 @@ use parse_it::{LexIt, LexerState};
 
 parse_it::parse_it! {
     #[lexer]
+    #[parse_it(longest_match = true)]
     mod lex {
         #[allow(dead_code)]
         #[derive(Debug)]
         pub enum Token<'a> {
             Integer(i64),
             String(String),
-            Ident(&'a str)
+            Ident(&'a str),
+            Punct(&'a str),
+            If,
+            Else,
+            Error(&'a str),
         }
 
         pub Initial -> Token<'lex> {
             r"\s" => continue,
+            r"/\*" .. r"\*/" => #[trivia],
             "\"" => {
                 let mut buf = String::new();
                 while lex!(StringLiteral(&mut buf)).is_some() {}
                 Token::String(buf)
             },
             Integer => Token::Integer(self),
-            r"[\p{XID_Start}_]\p{XID_Continue}*" => Token::Ident(self),
+            r"[\p{XID_Start}_]\p{XID_Continue}*" => Token::Ident(self) where {
+                "if" => Token::If,
+                "else" => Token::Else,
+            },
+            // the longest match wins, regardless of the order of the rules
+            "=" => Token::Punct(self),
+            "==" => Token::Punct(self),
+            _ => Token::Error(self),
         }
 
         Integer -> i64 {
@@ -39,12 +52,16 @@ parse_it::parse_it! {
 fn main() {
     let src = r#"
         "Hello, World!"
-        42
-        identifier
+        42 /* a /* nested */ comment */
+        if identifier == other else iffy
+        $
     "#;
     let lexer = lex::Initial::new();
     let mut lexbuf = LexerState::new(src);
-    while let Some(token) = lexer.next(&mut lexbuf) {
-        println!("{token:?}");
+    for result in lexbuf.tokens(&lexer) {
+        match result {
+            Ok((token, span)) => println!("{token:?} at {}..{}", span.start, span.end),
+            Err(err) => println!("{err:?}"),
+        }
     }
 }
@@ -0,0 +1,122 @@
+use std::{
+    io::{BufReader, Read},
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use parse_it::{lexer::Span, ParseIt};
+
+static ENTRIES_PARSED: AtomicUsize = AtomicUsize::new(0);
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Level {
+    Info,
+    Warn,
+    Error,
+}
+
+parse_it::parse_it! {
+    #[lexer]
+    mod lex {
+        use super::Level;
+
+        #[derive(Debug)]
+        pub enum Token<'a> {
+            Time(u64),
+            Level(Level),
+            Message(&'a str),
+        }
+
+        pub Initial -> Token<'lex> {
+            r"[0-9]+" => Token::Time(self.parse().unwrap()),
+            r"\[[A-Z]+\]" => match self {
+                "[INFO]" => Token::Level(Level::Info),
+                "[WARN]" => Token::Level(Level::Warn),
+                _ => Token::Level(Level::Error),
+            },
+            r" [^\n]*\n" => Token::Message(self[1..].trim_end()),
+        }
+    }
+
+    #[parser]
+    mod parse {
+        use super::{lex::Token, Level, ENTRIES_PARSED};
+        use std::sync::atomic::Ordering;
+
+        type Lexer = super::lex::Initial;
+
+        pub Entry -> (u64, Level, String) {
+            Token::Time(time) Token::Level(level) Token::Message(message) => {
+                ENTRIES_PARSED.fetch_add(1, Ordering::Relaxed);
+                (time, level, message.to_string())
+            }
+        }
+
+        pub Request -> (u64, u64) {
+            first:Entry Entry Entry Entry Entry last:Entry => (first.0, last.0),
+        }
+    }
+}
+
+/// A log file produced on the fly, standing in for a file too large to read
+/// at once.
+struct Log {
+    entries: u64,
+    line: Vec<u8>,
+}
+
+impl Read for Log {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if self.line.is_empty() && self.entries > 0 {
+            self.entries -= 1;
+            let level = ["INFO", "WARN", "ERROR"][self.entries as usize % 3];
+            self.line = format!(
+                "{}[{level}] request {} done\n",
+                1000 + self.entries,
+                self.entries
+            )
+            .into_bytes();
+        }
+        let len = self.line.len().min(buf.len());
+        buf[..len].copy_from_slice(&self.line[..len]);
+        self.line.drain(..len);
+        Ok(len)
+    }
+}
+
+fn main() {
+    let parser = parse::Entry::default();
+    let log = Log {
+        entries: 10_000,
+        line: vec![],
+    };
+    let mut errors = 0;
+    for entry in parser.parse_reader(BufReader::with_capacity(64, log)) {
+        let (_, level, message) = entry.unwrap();
+        if level == Level::Error {
+            errors += 1;
+            assert!(message.ends_with(" done"));
+        }
+    }
+    println!("{errors} errors");
+    assert_eq!(errors, 3333);
+
+    let input = "1[INFO] ok\n2[INFO] ok\n3 [WARN] not ok\n";
+    let entries = parser
+        .parse_reader(BufReader::with_capacity(8, input.as_bytes()))
+        .collect::<Vec<_>>();
+    assert_eq!(entries.len(), 3);
+    let error = entries[2].as_ref().unwrap_err();
+    println!("{error:?}");
+    assert_eq!(error.span, Span { start: 22, end: 22 });
+
+    // the entries of a request are not parsed again as more input is read
+    let parser = parse::Request::default();
+    let log = Log {
+        entries: 60,
+        line: vec![],
+    };
+    ENTRIES_PARSED.store(0, Ordering::Relaxed);
+    let requests = parser.parse_reader(BufReader::with_capacity(8, log));
+    assert_eq!(requests.count(), 10);
+    assert_eq!(ENTRIES_PARSED.load(Ordering::Relaxed), 60);
+}
@@ -0,0 +1,76 @@
+use parse_it::ParseIt;
+
+parse_it::parse_it! {
+    #[lexer]
+    mod lex {
+        #[derive(Debug)]
+        pub enum Token<'a> {
+            Ident(&'a str),
+            Text(&'a str),
+            #[token("+")]
+            Plus,
+            #[token("\"")]
+            Quote,
+            #[token("${")]
+            Interp,
+            #[token("}")]
+            RBrace,
+        }
+
+        pub Initial -> Token<'lex> {
+            r"\s+" => continue,
+            r"[A-Za-z_]\w*" => Token::Ident(self),
+            r"\+" => Token::Plus,
+            "\"" => {
+                push!(InString);
+                Token::Quote
+            }
+            r"\}" => {
+                pop!();
+                Token::RBrace
+            }
+        }
+
+        InString -> Token<'lex> {
+            "\"" => {
+                pop!();
+                Token::Quote
+            }
+            r"\$\{" => {
+                push!(Initial);
+                Token::Interp
+            }
+            r#"[^"$]+"# => Token::Text(self),
+        }
+    }
+
+    #[parser]
+    mod parse {
+        use super::lex::Token;
+
+        type Lexer = super::lex::Initial;
+
+        pub Expr -> String {
+            lhs:Expr '+' rhs:Atom => lhs + &rhs,
+            Atom => self,
+        }
+
+        Atom -> String {
+            Token::Ident(name) => name.to_uppercase(),
+            '"' parts:Segment* '"' => parts.concat(),
+        }
+
+        Segment -> String {
+            Token::Text(text) => text.to_string(),
+            "${" e:Expr '}' => e,
+        }
+    }
+}
+
+fn main() {
+    let parser = parse::Expr::default();
+    let src = r#"greeting + ", ${ "dear " + name } ${ "and ${ "the" + " " + others }" }!""#;
+    let result = parser.parse(src).unwrap();
+    println!("{result}");
+    assert_eq!(result, "GREETING, dear NAME and the OTHERS!");
+}
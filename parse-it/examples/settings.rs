@@ -0,0 +1,50 @@
+use parse_it::ParseIt;
+
+parse_it::parse_it! {
+    #[parser]
+    mod parse {
+        type Lexer = parse_it::CharLexer;
+
+        #[parse_it(iter(Setting, sep = ';'))]
+        pub Settings -> Vec<(String, u32)> {
+            first:Setting rest:(';' Setting)* ';'? => {
+                let mut settings = vec![first];
+                settings.extend(rest);
+                settings
+            }
+        }
+
+        Setting -> (String, u32) {
+            // names may be qualified by a section, and values have no units
+            section:(r"[a-z]+"re '.')? name:r"[a-z]+"re '=' value:r"[0-9]+"re !r"[a-z]"re => {
+                let name = match section {
+                    Some(section) => format!("{section}.{name}"),
+                    None => name.to_string(),
+                };
+                (name, value.parse().unwrap())
+            }
+        }
+    }
+}
+
+fn main() {
+    let parser = parse::Settings::default();
+    let src = "width=80;height=25;";
+    assert_eq!(parser.parse(src).unwrap().len(), 2);
+    assert!(parser.parse("width=80px;").is_err());
+    let settings = parser.parse("window.width=80;height=25;").unwrap();
+    assert_eq!(settings[0], ("window.width".to_string(), 80));
+    assert_eq!(settings[1], ("height".to_string(), 25));
+
+    // the settings come one at a time, skipping over the broken ones
+    let mut settings = parser.parse_iter("width=80;height=x;depth=3");
+    assert_eq!(settings.next().unwrap().unwrap(), ("width".to_string(), 80));
+    assert!(settings.next().unwrap().is_err());
+    assert_eq!(settings.next().unwrap().unwrap(), ("depth".to_string(), 3));
+    assert!(settings.next().is_none());
+
+    for setting in parser.parse_iter("width=80;height=25;") {
+        let (name, value) = setting.unwrap();
+        println!("{name} = {value}");
+    }
+}
@@ -0,0 +1,58 @@
+use parse_it::ParseIt;
+
+parse_it::parse_it! {
+    #[lexer]
+    #[parse_it(case_insensitive = true, unicode = false)]
+    mod lex {
+        #[derive(Debug)]
+        pub enum Token<'a> {
+            Ident(&'a str),
+            #[token("select")]
+            Select,
+            #[token("from")]
+            From,
+            #[token(",")]
+            Comma,
+        }
+
+        pub Initial -> Token<'lex> {
+            r"\s+" => continue,
+            r"[a-z_]\w*" => Token::Ident(self) where {
+                "select" => Token::Select,
+                "from" => Token::From,
+            },
+            "," => Token::Comma,
+        }
+    }
+
+    #[parser]
+    mod parse {
+        use super::lex::Token;
+
+        type Lexer = super::lex::Initial;
+
+        pub Query -> (Vec<String>, String) {
+            "select" columns:Columns "from" Token::Ident(table) => (columns, table.to_string()),
+        }
+
+        Columns -> Vec<String> {
+            first:Column rest:("," Column)* => std::iter::once(first).chain(rest).collect(),
+        }
+
+        Column -> String {
+            Token::Ident(column) => column.to_string(),
+        }
+    }
+}
+
+fn main() {
+    let parser = parse::Query::default();
+    let (columns, table) = parser.parse("SELECT Name, age FROM Users").unwrap();
+    println!("{columns:?} from {table}");
+    assert_eq!(columns, ["Name", "age"]);
+    assert_eq!(table, "Users");
+
+    // identifiers are ASCII-only
+    let parser = parse::Query::default();
+    assert!(parser.parse("select naïve from t").is_err());
+}
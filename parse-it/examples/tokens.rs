@@ -0,0 +1,101 @@
+use parse_it::{lexer::Span, ParseIt};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Token {
+    Ident(String),
+    Number(i64),
+    Punct(char),
+}
+
+/// A tokenizer standing in for an existing front-end.
+fn tokenize(src: &str) -> Vec<(Token, Span)> {
+    let mut tokens = vec![];
+    let mut chars = src.char_indices().peekable();
+    while let Some((start, ch)) = chars.next() {
+        let mut end = start + ch.len_utf8();
+        while let Some(&(i, c)) = chars.peek() {
+            if !(ch.is_alphanumeric() && c.is_alphanumeric()) {
+                break;
+            }
+            end = i + c.len_utf8();
+            chars.next();
+        }
+        let text = &src[start..end];
+        let token = if ch.is_whitespace() {
+            continue;
+        } else if let Ok(n) = text.parse() {
+            Token::Number(n)
+        } else if ch.is_alphabetic() {
+            Token::Ident(text.to_string())
+        } else {
+            Token::Punct(ch)
+        };
+        tokens.push((token, Span { start, end }));
+    }
+    tokens
+}
+
+parse_it::parse_it! {
+    #[parser]
+    mod parse {
+        use super::Token;
+
+        type Lexer = parse_it::lexer::SliceLexer<Token>;
+
+        pub Bindings -> Vec<(String, i64)> {
+            Binding+ => self,
+        }
+
+        Binding -> (String, i64) {
+            Token::Ident(name) Token::Punct('=') value:Value Token::Punct(';') => (name, value),
+        }
+
+        Value -> i64 {
+            Token::Number(n) => n,
+            Token::Punct('-') Token::Number(n) => -n,
+        }
+    }
+
+    #[parser]
+    mod signed {
+        type Lexer = parse_it::lexer::SliceLexer<(char, i64)>;
+
+        pub Total -> i64 {
+            values:Signed* => values.into_iter().sum(),
+        }
+
+        // the tokens are tuples themselves
+        Signed -> i64 {
+            ('+', n) => n,
+            ('-', n) => -n,
+        }
+    }
+}
+
+fn main() {
+    let parser = parse::Bindings::default();
+    let tokens = tokenize("x = 1; y = -20;");
+    let bindings = parser.parse_tokens(&tokens).unwrap();
+    println!("{bindings:?}");
+    assert_eq!(bindings, [("x".to_string(), 1), ("y".to_string(), -20)]);
+
+    let parser = parse::Bindings::default();
+    assert!(parser.parse_tokens(&tokenize("x = y;")).is_err());
+
+    let parser = signed::Total::default();
+    // one character per token
+    let tokens: Vec<_> = [('+', 3), ('-', 5), ('+', 4)]
+        .into_iter()
+        .zip(0..)
+        .map(|(token, start)| {
+            (
+                token,
+                Span {
+                    start,
+                    end: start + 1,
+                },
+            )
+        })
+        .collect();
+    assert_eq!(parser.parse_tokens(&tokens).unwrap(), 2);
+}
@@ -0,0 +1,509 @@
+//! Concrete syntax trees.
+//!
+//! Parser modules marked with `#[parse_it(cst)]` record a node of a concrete
+//! syntax tree for each rule they apply, along with the values computed by
+//! their actions. The kind of a node is the name of its rule, and its children
+//! are the tokens consumed by the rule and the nodes of the rules it applied,
+//! in order. Each parser of such a module gets a `parse_cst` method returning
+//! the tree along with the value.
+//!
+//! The input the lexer skips between the tokens, such as whitespace and
+//! comments, is recorded as trivia tokens, so that the tokens of a tree
+//! reproduce the parsed input. The trivia before the first token of a node
+//! belongs to its parent, so that the node spans its own tokens only.
+//!
+//! The tree is made of two layers, in the manner of [rowan]: the immutable
+//! [`GreenNode`]s record what the parsers built, and the [`SyntaxNode`]s wrap
+//! them with their parents, for navigating the tree in all directions.
+//!
+//! The tree keeps the source bytes, so the text of a node is only `None` if
+//! its bytes are not valid UTF-8, e.g. with a [`ByteLexer`]. Trees parsed from
+//! pre-lexed tokens, e.g. with a [`SliceLexer`], have no source, so the bytes
+//! of their nodes are empty.
+//!
+//! [`ByteLexer`]: crate::ByteLexer
+//! [`SliceLexer`]: crate::lexer::SliceLexer
+//!
+//! ```
+//! parse_it::parse_it! {
+//!     #[parser]
+//!     #[parse_it(cst)]
+//!     mod parse {
+//!         type Lexer = parse_it::CharLexer;
+//!
+//!         pub Sum -> u32 {
+//!             lhs:Sum '+' rhs:Num => lhs + rhs,
+//!             Num => self,
+//!         }
+//!
+//!         Num -> u32 {
+//!             digits:r"[0-9]+"re => digits.parse().unwrap(),
+//!         }
+//!     }
+//! }
+//!
+//! let (value, root) = parse::Sum::default().parse_cst("1+20").unwrap();
+//! assert_eq!(value, 21);
+//! assert_eq!(root.kind(), "Sum");
+//! assert_eq!(root.text(), Some("1+20"));
+//!
+//! let num = root.last_child().unwrap();
+//! assert_eq!((num.kind(), num.text()), ("Num", Some("20")));
+//! assert_eq!(num.prev_sibling().unwrap().text(), Some("1"));
+//! assert_eq!(num.parent().unwrap(), root);
+//! ```
+//!
+//! [rowan]: https://docs.rs/rowan
+
+use std::{cell::RefCell, fmt, rc::Rc};
+
+use rustc_hash::FxHashMap;
+
+use crate::{
+    lexer::{Cursor, Span},
+    Error, LexIt, ParseIt, ParserState,
+};
+
+/// A child of a [`GreenNode`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum GreenElement {
+    /// The node of a rule applied by the parent rule.
+    Node(Rc<GreenNode>),
+    /// A token consumed by the parent rule, at the given span.
+    Token(Span),
+    /// The input skipped by the lexer before a token, at the given span.
+    Trivia(Span),
+}
+
+impl GreenElement {
+    /// The span of the element in the source.
+    pub fn span(&self) -> Span {
+        match self {
+            GreenElement::Node(node) => node.span,
+            GreenElement::Token(span) | GreenElement::Trivia(span) => *span,
+        }
+    }
+}
+
+/// A node recorded by a rule, without any link to its parent.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GreenNode {
+    kind: &'static str,
+    span: Span,
+    children: Vec<GreenElement>,
+}
+
+impl GreenNode {
+    /// Create a node of the given kind. The node spans its children, or is
+    /// empty at `start` if it has none.
+    pub fn new(kind: &'static str, start: usize, children: Vec<GreenElement>) -> Self {
+        let span = match (children.first(), children.last()) {
+            (Some(first), Some(last)) => Span {
+                start: first.span().start,
+                end: last.span().end,
+            },
+            _ => Span { start, end: start },
+        };
+        Self {
+            kind,
+            span,
+            children,
+        }
+    }
+
+    /// The kind of the node, i.e. the name of the rule.
+    pub fn kind(&self) -> &'static str {
+        self.kind
+    }
+
+    /// The span of the node in the source.
+    pub fn span(&self) -> Span {
+        self.span
+    }
+
+    /// The children of the node.
+    pub fn children(&self) -> &[GreenElement] {
+        &self.children
+    }
+}
+
+/// The nodes recorded by a rule, along with the trivia before them, by the
+/// positions it parsed them between.
+///
+/// This stands beside the memo of the rule, so that the node of a memoized
+/// result is found again.
+#[doc(hidden)]
+#[derive(Debug, Default)]
+pub struct Nodes {
+    nodes: RefCell<FxHashMap<(Cursor, Cursor), Vec<GreenElement>>>,
+}
+
+impl Nodes {
+    /// Clear all recorded nodes.
+    pub fn clear(&self) {
+        self.nodes.borrow_mut().clear();
+    }
+}
+
+/// Record the node of a rule, whose actual parsing is `parser`.
+#[doc(hidden)]
+pub fn build<L: LexIt + Clone, T>(
+    state: &mut ParserState<L>,
+    nodes: &Nodes,
+    kind: &'static str,
+    parser: impl FnOnce(&mut ParserState<L>) -> Result<T, Error>,
+) -> Result<T, Error> {
+    if !state.records_cst() {
+        return parser(state);
+    }
+    let start = state.cursor();
+    let offset = state.lexeme_end();
+    let mark = state.cst_mark();
+    let value = parser(state)?;
+    let mut children = state.cst_take(mark);
+    // the trivia before the first token is left to the parent
+    let trivia = match children.first() {
+        Some(GreenElement::Trivia(_)) => Some(children.remove(0)),
+        _ => None,
+    };
+    let node = Rc::new(GreenNode::new(kind, offset, children));
+    let elements = trivia.into_iter().chain([GreenElement::Node(node)]);
+    nodes
+        .nodes
+        .borrow_mut()
+        .insert((start, state.cursor()), elements.collect());
+    Ok(value)
+}
+
+/// Add the node of a rule to the tree, whether `parser` parsed it anew with
+/// [`build`] or found it in a memo.
+#[doc(hidden)]
+pub fn record<L: LexIt + Clone, T>(
+    state: &mut ParserState<L>,
+    nodes: &Nodes,
+    parser: impl FnOnce(&mut ParserState<L>) -> Result<T, Error>,
+) -> Result<T, Error> {
+    if !state.records_cst() {
+        return parser(state);
+    }
+    let start = state.cursor();
+    let mark = state.cst_mark();
+    let value = parser(state)?;
+    let elements = nodes.nodes.borrow().get(&(start, state.cursor())).cloned();
+    for (i, element) in elements.into_iter().flatten().enumerate() {
+        state.cst_push(mark + i, element);
+    }
+    Ok(value)
+}
+
+/// Parse the given input, recording its concrete syntax tree.
+///
+/// The input is a string, or a [`ParserState`] for lexers that are constructed
+/// with their inputs. The parser must belong to a parser module marked with
+/// `#[parse_it(cst)]`. The root of the tree is the node of the parser, along
+/// with the trivia around it: from the start of the input, and to its end if
+/// no token is left, so that its text is all the parsed input.
+pub fn parse_cst<'a, P: ParseIt>(
+    parser: &P,
+    input: impl Into<ParserState<'a, P::Lexer>>,
+) -> Result<(P::Output, SyntaxNode), Error> {
+    let mut state = input.into();
+    let input = state.source_bytes();
+    state.record_cst(true);
+    let value = parser.parse_stream(&mut state)?;
+    let mut children = state.cst_take(0);
+    let end = state.lexeme_end();
+    if state.at_end() && end < input.len() {
+        let span = Span {
+            start: end,
+            end: input.len(),
+        };
+        children.push(GreenElement::Trivia(span));
+    }
+    let mut nodes = children
+        .iter()
+        .enumerate()
+        .filter(|(_, child)| matches!(child, GreenElement::Node(_)));
+    let root = match (nodes.next(), nodes.next()) {
+        // the node of the parser takes in the trivia around it
+        (Some((i, GreenElement::Node(node))), None) => {
+            let (i, node) = (i, node.clone());
+            children.splice(i..=i, node.children.iter().cloned());
+            GreenNode::new(node.kind, node.span.start, children)
+        }
+        _ => GreenNode::new("", 0, children),
+    };
+    let root = SyntaxNode::new_root(Rc::new(root), input.into());
+    Ok((value, root))
+}
+
+struct NodeData {
+    green: Rc<GreenNode>,
+    parent: Option<SyntaxNode>,
+    index: usize,
+    source: Rc<[u8]>,
+}
+
+/// A node of a concrete syntax tree, with its parent.
+///
+/// Nodes are cheap to clone, and compare equal if they are the same node of
+/// the same tree.
+#[derive(Clone)]
+pub struct SyntaxNode(Rc<NodeData>);
+
+/// A token of a concrete syntax tree, with its parent.
+#[derive(Clone, PartialEq, Eq)]
+pub struct SyntaxToken {
+    parent: SyntaxNode,
+    index: usize,
+    span: Span,
+}
+
+/// A child of a [`SyntaxNode`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SyntaxElement {
+    /// A node.
+    Node(SyntaxNode),
+    /// A token, or trivia.
+    Token(SyntaxToken),
+}
+
+impl SyntaxNode {
+    /// Create the root of a tree over the given source.
+    pub fn new_root(green: Rc<GreenNode>, source: Rc<[u8]>) -> Self {
+        Self(Rc::new(NodeData {
+            green,
+            parent: None,
+            index: 0,
+            source,
+        }))
+    }
+
+    /// The kind of the node, i.e. the name of the rule.
+    pub fn kind(&self) -> &'static str {
+        self.0.green.kind
+    }
+
+    /// The span of the node in the source.
+    pub fn span(&self) -> Span {
+        self.0.green.span
+    }
+
+    /// The source bytes of the node, including any trivia between its tokens.
+    pub fn bytes(&self) -> &[u8] {
+        let Span { start, end } = self.span();
+        self.0.source.get(start..end).unwrap_or_default()
+    }
+
+    /// The source text of the node, including any trivia between its tokens,
+    /// or `None` if its bytes are not valid UTF-8.
+    pub fn text(&self) -> Option<&str> {
+        std::str::from_utf8(self.bytes()).ok()
+    }
+
+    /// The underlying green node.
+    pub fn green(&self) -> &Rc<GreenNode> {
+        &self.0.green
+    }
+
+    /// The parent of the node, unless it is the root.
+    pub fn parent(&self) -> Option<SyntaxNode> {
+        self.0.parent.clone()
+    }
+
+    /// The ancestors of the node, starting from its parent.
+    pub fn ancestors(&self) -> impl Iterator<Item = SyntaxNode> {
+        std::iter::successors(self.parent(), SyntaxNode::parent)
+    }
+
+    /// The child nodes of the node.
+    pub fn children(&self) -> impl Iterator<Item = SyntaxNode> {
+        self.children_with_tokens()
+            .filter_map(SyntaxElement::into_node)
+    }
+
+    /// The children of the node, both nodes and tokens.
+    pub fn children_with_tokens(&self) -> impl Iterator<Item = SyntaxElement> {
+        let this = self.clone();
+        (0..self.0.green.children.len()).map(move |index| this.child(index))
+    }
+
+    /// The first child node.
+    pub fn first_child(&self) -> Option<SyntaxNode> {
+        self.children().next()
+    }
+
+    /// The last child node.
+    pub fn last_child(&self) -> Option<SyntaxNode> {
+        self.children().last()
+    }
+
+    /// The next sibling node.
+    pub fn next_sibling(&self) -> Option<SyntaxNode> {
+        let parent = self.parent()?;
+        let len = parent.0.green.children.len();
+        (self.0.index + 1..len).find_map(|index| parent.child(index).into_node())
+    }
+
+    /// The previous sibling node.
+    pub fn prev_sibling(&self) -> Option<SyntaxNode> {
+        let parent = self.parent()?;
+        (0..self.0.index)
+            .rev()
+            .find_map(|index| parent.child(index).into_node())
+    }
+
+    /// The node and all nodes below it, in preorder.
+    pub fn descendants(&self) -> impl Iterator<Item = SyntaxNode> {
+        let mut stack = vec![self.clone()];
+        std::iter::from_fn(move || {
+            let node = stack.pop()?;
+            let mut children = node.children().collect::<Vec<_>>();
+            children.reverse();
+            stack.extend(children);
+            Some(node)
+        })
+    }
+
+    /// All tokens below the node, in order, trivia included.
+    pub fn tokens(&self) -> impl Iterator<Item = SyntaxToken> {
+        let mut stack = vec![SyntaxElement::Node(self.clone())];
+        std::iter::from_fn(move || loop {
+            match stack.pop()? {
+                SyntaxElement::Node(node) => {
+                    let mut children = node.children_with_tokens().collect::<Vec<_>>();
+                    children.reverse();
+                    stack.extend(children);
+                }
+                SyntaxElement::Token(token) => return Some(token),
+            }
+        })
+    }
+
+    fn child(&self, index: usize) -> SyntaxElement {
+        match &self.0.green.children[index] {
+            GreenElement::Node(green) => SyntaxElement::Node(SyntaxNode(Rc::new(NodeData {
+                green: green.clone(),
+                parent: Some(self.clone()),
+                index,
+                source: self.0.source.clone(),
+            }))),
+            GreenElement::Token(span) | GreenElement::Trivia(span) => {
+                SyntaxElement::Token(SyntaxToken {
+                    parent: self.clone(),
+                    index,
+                    span: *span,
+                })
+            }
+        }
+    }
+}
+
+impl PartialEq for SyntaxNode {
+    fn eq(&self, other: &Self) -> bool {
+        Rc::ptr_eq(&self.0.green, &other.0.green)
+            && self.0.index == other.0.index
+            && self.0.parent == other.0.parent
+    }
+}
+
+impl Eq for SyntaxNode {}
+
+impl fmt::Debug for SyntaxNode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let Span { start, end } = self.span();
+        write!(f, "{}@{start}..{end}", self.kind())
+    }
+}
+
+impl fmt::Display for SyntaxNode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&String::from_utf8_lossy(self.bytes()))
+    }
+}
+
+impl SyntaxToken {
+    /// The span of the token in the source.
+    pub fn span(&self) -> Span {
+        self.span
+    }
+
+    /// The source bytes of the token.
+    pub fn bytes(&self) -> &[u8] {
+        let Span { start, end } = self.span;
+        self.parent.0.source.get(start..end).unwrap_or_default()
+    }
+
+    /// The source text of the token, or `None` if its bytes are not valid
+    /// UTF-8.
+    pub fn text(&self) -> Option<&str> {
+        std::str::from_utf8(self.bytes()).ok()
+    }
+
+    /// Whether the token is trivia skipped by the lexer.
+    pub fn is_trivia(&self) -> bool {
+        matches!(
+            self.parent.0.green.children[self.index],
+            GreenElement::Trivia(_)
+        )
+    }
+
+    /// The node of the rule that consumed the token.
+    pub fn parent(&self) -> SyntaxNode {
+        self.parent.clone()
+    }
+
+    /// The next sibling, either a node or a token.
+    pub fn next_sibling_or_token(&self) -> Option<SyntaxElement> {
+        let len = self.parent.0.green.children.len();
+        (self.index + 1 < len).then(|| self.parent.child(self.index + 1))
+    }
+
+    /// The previous sibling, either a node or a token.
+    pub fn prev_sibling_or_token(&self) -> Option<SyntaxElement> {
+        (self.index > 0).then(|| self.parent.child(self.index - 1))
+    }
+}
+
+impl fmt::Debug for SyntaxToken {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let Span { start, end } = self.span;
+        write!(
+            f,
+            "{:?}@{start}..{end}",
+            String::from_utf8_lossy(self.bytes())
+        )
+    }
+}
+
+impl fmt::Display for SyntaxToken {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&String::from_utf8_lossy(self.bytes()))
+    }
+}
+
+impl SyntaxElement {
+    /// The span of the element in the source.
+    pub fn span(&self) -> Span {
+        match self {
+            SyntaxElement::Node(node) => node.span(),
+            SyntaxElement::Token(token) => token.span(),
+        }
+    }
+
+    /// The node, if the element is one.
+    pub fn into_node(self) -> Option<SyntaxNode> {
+        match self {
+            SyntaxElement::Node(node) => Some(node),
+            SyntaxElement::Token(_) => None,
+        }
+    }
+
+    /// The token, if the element is one.
+    pub fn into_token(self) -> Option<SyntaxToken> {
+        match self {
+            SyntaxElement::Node(_) => None,
+            SyntaxElement::Token(token) => Some(token),
+        }
+    }
+}
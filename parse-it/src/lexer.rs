@@ -1,10 +1,39 @@
 //! Lexing for the parser.
 
-use std::{hash::Hash, rc::Rc};
+use std::{
+    cell::RefCell,
+    collections::{BTreeMap, BTreeSet},
+    hash::Hash,
+    ops::Range,
+    rc::Rc,
+};
 
-use regex_automata::{Anchored, Input, PatternID};
+use regex_automata::{
+    dfa::{sparse, Automaton},
+    Anchored, HalfMatch, Input, PatternID,
+};
+use rustc_hash::FxHashMap;
 
-pub use regex_automata::meta::Regex;
+pub use parse_it_macros::TryConvert;
+pub use regex_automata::{meta::Regex, MatchKind};
+
+/// A DFA of lexer rules, compiled at macro expansion time.
+///
+/// No regex is compiled at runtime: the DFA is embedded in the binary, and
+/// only checked once, on first use, in time linear in its size.
+pub type Dfa = sparse::DFA<&'static [u8]>;
+
+/// Deserialize a DFA compiled at macro expansion time.
+///
+/// The bytes are borrowed, not copied, but they are validated, which takes
+/// time linear in the size of the DFA. Generated code does this once, on
+/// first use of each DFA.
+#[doc(hidden)]
+pub fn load_dfa(bytes: &'static [u8]) -> Dfa {
+    sparse::DFA::from_bytes(bytes)
+        .expect("the DFA is serialized for this target")
+        .0
+}
 
 use crate::{LexIt, Memo};
 
@@ -17,7 +46,113 @@ pub struct Span {
     pub end: usize,
 }
 
+/// An error that occurred during lexing.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LexError {
+    /// The span in the source code where the error occurred.
+    pub span: Span,
+    /// The kind of the error.
+    pub kind: LexErrorKind,
+}
+
+/// The kind of a [`LexError`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LexErrorKind {
+    /// No lexer rule matches at the given character.
+    UnexpectedChar(char),
+    /// No lexer rule matches at the given byte, which does not start a valid
+    /// UTF-8 sequence.
+    UnexpectedByte(u8),
+    /// The input ended in the middle of a token, e.g. an unterminated string.
+    UnexpectedEof,
+    /// The input ended between two tokens.
+    ///
+    /// This is how a lexer signals the end of the token stream: [`LexIt::next`]
+    /// turns it into `Ok(None)`.
+    EndOfInput,
+    /// A line is dedented to a width that matches no enclosing indentation
+    /// level.
+    InconsistentDedent,
+    /// A lexer over pre-lexed tokens has no token at the given position,
+    /// because the lexer state was not created for it, e.g. over source text.
+    MissingToken,
+}
+
+impl LexError {
+    /// Treat the end of input as unexpected, as a token is still in progress.
+    ///
+    /// This is applied to the errors of sub-lexers invoked from lexer actions.
+    pub fn unterminated(self) -> Self {
+        match self.kind {
+            LexErrorKind::EndOfInput => Self {
+                kind: LexErrorKind::UnexpectedEof,
+                ..self
+            },
+            _ => self,
+        }
+    }
+}
+
+/// Turn the end of input into the end of the token stream.
+#[doc(hidden)]
+pub fn end_of_stream<T>(result: Result<Option<T>, LexError>) -> Result<Option<T>, LexError> {
+    match result {
+        Err(LexError {
+            kind: LexErrorKind::EndOfInput,
+            ..
+        }) => Ok(None),
+        result => result,
+    }
+}
+
+/// Search for an anchored match of the DFA, along with the extent of the
+/// input examined, which is where the DFA stops being able to match.
+fn search_dfa<A: Automaton>(dfa: &A, input: &Input) -> (Option<HalfMatch>, usize) {
+    let haystack = input.haystack();
+    let Ok(mut sid) = dfa.start_state_forward(input) else {
+        return (None, haystack.len() + 1);
+    };
+    let mut end = None;
+    for at in input.start()..input.end() {
+        sid = dfa.next_state(sid, haystack[at]);
+        if dfa.is_special_state(sid) {
+            if dfa.is_match_state(sid) {
+                // matches are reported one byte late
+                end = Some(HalfMatch::new(dfa.match_pattern(sid, 0), at));
+            } else if dfa.is_dead_state(sid) {
+                return (end, at + 1);
+            } else if dfa.is_quit_state(sid) {
+                return (dfa.try_search_fwd(input).ok().flatten(), haystack.len() + 1);
+            }
+        }
+    }
+    sid = dfa.next_eoi_state(sid);
+    if dfa.is_match_state(sid) {
+        end = Some(HalfMatch::new(dfa.match_pattern(sid, 0), input.end()));
+    }
+    (end, haystack.len() + 1)
+}
+
 /// A trait for types that can be converted to another type.
+///
+/// This is what typed terminals `<T>` in parser productions rely on: a token
+/// matches `<T>` if it can be converted to `T`, and the converted value is
+/// captured. For token enums, the trait can be derived, which generates a
+/// `TryConvert<T>` impl for every variant wrapping a single value of type `T`.
+///
+/// ```
+/// use parse_it::lexer::TryConvert;
+///
+/// #[derive(TryConvert)]
+/// enum Token {
+///     Number(f64),
+///     String(String),
+///     Punct,
+/// }
+///
+/// assert_eq!(Token::Number(1.0).try_convert(), Some(1.0));
+/// assert_eq!(TryConvert::<String>::try_convert(&Token::Punct), None);
+/// ```
 pub trait TryConvert<T> {
     /// Try to convert the value to the target type.
     fn try_convert(&self) -> Option<T>;
@@ -29,11 +164,151 @@ impl<T: Copy> TryConvert<T> for T {
     }
 }
 
+/// The characters whose simple case folding is not their lowercase, along with
+/// the lowercase they fold with, sorted.
+const SIMPLE_FOLDS: &[(char, char)] = &[
+    ('\u{b5}', '\u{3bc}'),
+    ('\u{17f}', 's'),
+    ('\u{345}', '\u{3b9}'),
+    ('\u{3c2}', '\u{3c3}'),
+    ('\u{3d0}', '\u{3b2}'),
+    ('\u{3d1}', '\u{3b8}'),
+    ('\u{3d5}', '\u{3c6}'),
+    ('\u{3d6}', '\u{3c0}'),
+    ('\u{3f0}', '\u{3ba}'),
+    ('\u{3f1}', '\u{3c1}'),
+    ('\u{3f5}', '\u{3b5}'),
+    ('\u{1c80}', '\u{432}'),
+    ('\u{1c81}', '\u{434}'),
+    ('\u{1c82}', '\u{43e}'),
+    ('\u{1c83}', '\u{441}'),
+    ('\u{1c84}', '\u{442}'),
+    ('\u{1c85}', '\u{442}'),
+    ('\u{1c86}', '\u{44a}'),
+    ('\u{1c87}', '\u{463}'),
+    ('\u{1c88}', '\u{a64b}'),
+    ('\u{1e9b}', '\u{1e61}'),
+    ('\u{1fbe}', '\u{3b9}'),
+    ('\u{1fd3}', '\u{390}'),
+    ('\u{1fe3}', '\u{3b0}'),
+    ('\u{fb05}', '\u{fb06}'),
+];
+
+/// Map a character to a representative of its class under Unicode simple case
+/// folding: its lowercase, unless that takes several characters.
+fn simple_fold(c: char) -> char {
+    if let Ok(i) = SIMPLE_FOLDS.binary_search_by_key(&c, |&(from, _)| from) {
+        return SIMPLE_FOLDS[i].1;
+    }
+    let mut lower = c.to_lowercase();
+    match (lower.next(), lower.next()) {
+        (Some(lower), None) => lower,
+        _ => c,
+    }
+}
+
+/// Whether two characters are equal under Unicode simple case folding.
+pub(crate) fn fold_eq(a: char, b: char) -> bool {
+    a == b || simple_fold(a) == simple_fold(b)
+}
+
+/// Whether two strings are equal under Unicode simple case folding, as
+/// keywords of case-insensitive lexers are matched.
+#[doc(hidden)]
+pub fn eq_ignore_case(a: &str, b: &str) -> bool {
+    a.chars().count() == b.chars().count() && a.chars().zip(b.chars()).all(|(a, b)| fold_eq(a, b))
+}
+
+/// Find the kind of a literal terminal in the literals of a lexer.
+///
+/// Returns `None` if the lexer does not define its literals.
+///
+/// # Panics
+/// Panics with the given message if the lexer defines its literals, but not
+/// the given one. This is meant to be evaluated in a const context, so that
+/// the panic becomes a compile-time error.
+#[doc(hidden)]
+pub const fn literal_kind<L: LexIt>(literal: &str, message: &str) -> Option<usize> {
+    const fn str_eq(a: &str, b: &str) -> bool {
+        let (a, b) = (a.as_bytes(), b.as_bytes());
+        if a.len() != b.len() {
+            return false;
+        }
+        let mut i = 0;
+        while i < a.len() {
+            if a[i] != b[i] {
+                return false;
+            }
+            i += 1;
+        }
+        true
+    }
+
+    let literals = L::LITERALS;
+    if literals.is_empty() {
+        return None;
+    }
+    let mut i = 0;
+    while i < literals.len() {
+        if str_eq(literals[i], literal) {
+            return Some(i);
+        }
+        i += 1;
+    }
+    panic!("{}", message)
+}
+
 /// Cursor position in the input.
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
 pub struct Cursor {
     cursor: usize,
     start: usize,
+    layout: usize,
+    modes: usize,
+}
+
+impl Cursor {
+    /// Whether the cursor, including the start of the token before it, is
+    /// after the given edit.
+    pub(crate) fn is_after(&self, edit: &Edit) -> bool {
+        self.start >= edit.range.end
+    }
+
+    /// Move a cursor after the given edit to its position in the edited text.
+    pub(crate) fn shift(self, edit: &Edit) -> Self {
+        Self {
+            cursor: edit.shift(self.cursor),
+            start: edit.shift(self.start),
+            layout: self.layout,
+            modes: self.modes,
+        }
+    }
+}
+
+/// An edit of the source text, for incremental reparsing.
+///
+/// See [`ParserState::edit`](crate::ParserState::edit).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Edit {
+    /// The replaced range of the old text.
+    pub range: Range<usize>,
+    /// The length of the replacement.
+    pub len: usize,
+}
+
+impl Edit {
+    /// Create an edit replacing the given range of the old text.
+    pub fn new(range: Range<usize>, replacement: &str) -> Self {
+        Self {
+            range,
+            len: replacement.len(),
+        }
+    }
+
+    /// Move a position after the edit to its position in the edited text.
+    pub(crate) fn shift(&self, pos: usize) -> usize {
+        pos - self.range.end + self.range.start + self.len
+    }
 }
 
 /// TODO
@@ -41,45 +316,311 @@ pub struct Cursor {
 pub struct LexerState<'a> {
     start: usize,
     cursor: usize,
-    input: &'a str,
-    memo: Rc<Memo<Cursor, (PatternID, *const Regex)>>,
+    input: &'a [u8],
+    text: Option<&'a str>,
+    spans: Option<Rc<RefCell<Vec<Span>>>>,
+    /// the spans of the pre-lexed tokens yet to be pulled
+    pending: Option<Rc<dyn Fn() -> Option<Span> + 'a>>,
+    memo: Rc<Memo<Cursor, (PatternID, *const ())>>,
+    modes: usize,
+    mode_stacks: Rc<RefCell<ModeStacks>>,
+    mode_changes: Rc<RefCell<BTreeSet<usize>>>,
+    trivia: Rc<RefCell<BTreeMap<usize, usize>>>,
+    pattern: Option<(PatternID, *const ())>,
+    layout: usize,
+    layouts: Rc<RefCell<BTreeMap<usize, LayoutRun>>>,
+    tab_width: usize,
+    examined: usize,
+}
+
+/// The mode stacks the lexer has been in, so that a cursor identifies its mode
+/// stack by an index, and forks that are abandoned leave no trace of the modes
+/// they switched to.
+///
+/// Each stack is a mode pushed onto another stack, and the empty stack is 0.
+#[derive(Default)]
+struct ModeStacks {
+    stacks: Vec<(usize, usize)>,
+    ids: FxHashMap<(usize, usize), usize>,
+}
+
+impl ModeStacks {
+    /// Get the stack of the given mode pushed onto the given stack.
+    fn push(&mut self, stack: usize, mode: usize) -> usize {
+        *self.ids.entry((stack, mode)).or_insert_with(|| {
+            self.stacks.push((stack, mode));
+            self.stacks.len()
+        })
+    }
+
+    /// Get the top of the given stack, and the stack below it.
+    fn top(&self, stack: usize) -> Option<(usize, usize)> {
+        stack.checked_sub(1).map(|index| self.stacks[index])
+    }
+}
+
+/// The synthetic tokens emitted at a line break by [`LexerState::layout`].
+#[derive(Clone)]
+struct LayoutRun {
+    pattern: Option<(PatternID, *const ())>,
+    tokens: Vec<Layout>,
+    indents: Rc<Vec<usize>>,
 }
 
 impl<'a> LexerState<'a> {
     /// Create a new lexer state.
     pub fn new(input: &'a str) -> Self {
+        Self {
+            text: Some(input),
+            ..Self::from_bytes(input.as_bytes())
+        }
+    }
+
+    /// Create a new lexer state over raw bytes, e.g. a binary format or an
+    /// input of unknown encoding.
+    ///
+    /// Regexes run against the bytes as they are, and lexemes are only
+    /// required to be valid UTF-8 when they are read as strings. Use
+    /// [`lexeme_bytes`](Self::lexeme_bytes) and [`ByteLexer`] to handle
+    /// arbitrary bytes.
+    pub fn from_bytes(input: &'a [u8]) -> Self {
         Self {
             start: 0,
             cursor: 0,
             input,
+            text: None,
+            spans: None,
+            pending: None,
             memo: Default::default(),
+            modes: 0,
+            mode_stacks: Default::default(),
+            mode_changes: Default::default(),
+            trivia: Default::default(),
+            pattern: None,
+            layout: 0,
+            layouts: Default::default(),
+            // the tab width of the last layout, to compute lost layouts again
+            tab_width: 8,
+            examined: 0,
+        }
+    }
+
+    /// Create a new lexer state over pre-lexed tokens with the given spans.
+    ///
+    /// The cursor then counts tokens instead of bytes, and
+    /// [`span`](Self::span) reports the spans of the tokens. There is no source
+    /// text, so lexemes are empty and regexes never match. See [`SliceLexer`].
+    pub fn from_spans(spans: impl IntoIterator<Item = Span>) -> Self {
+        Self {
+            spans: Some(Rc::new(RefCell::new(spans.into_iter().collect()))),
+            ..Self::from_bytes(&[])
         }
     }
 
+    /// Create a new lexer state over pre-lexed tokens whose spans are pulled
+    /// from the given iterator on demand, as in
+    /// [`from_spans`](Self::from_spans).
+    ///
+    /// The iterator may borrow for the lifetime of the lexer state, e.g. the
+    /// source text of an external lexer. See [`IterLexer`].
+    pub fn from_span_iter<I>(spans: I) -> Self
+    where
+        I: IntoIterator<Item = Span>,
+        I::IntoIter: 'a,
+    {
+        let spans = RefCell::new(spans.into_iter().fuse());
+        Self {
+            pending: Some(Rc::new(move || spans.borrow_mut().next())),
+            ..Self::from_spans([])
+        }
+    }
+
+    /// Pull the spans of pre-lexed tokens until `len` of them are known, or
+    /// there are no more.
+    fn pull_spans(&self, len: usize) {
+        let (Some(spans), Some(pending)) = (&self.spans, &self.pending) else {
+            return;
+        };
+        while spans.borrow().len() < len {
+            let Some(span) = pending() else {
+                return;
+            };
+            spans.borrow_mut().push(span);
+        }
+    }
+
+    /// Create a lexer state over the edited text, keeping the trivia, mode
+    /// changes and layout of the unaffected parts of this one.
+    ///
+    /// Returns the edit with its range extended to the end of the input
+    /// if the lexer modes or layout after the edit may have changed, as every
+    /// token after it then needs to be lexed again.
+    pub fn edit<'b>(&self, input: &'b str, edit: &Edit) -> (LexerState<'b>, Edit) {
+        let (lexbuf, edit) = self.edit_bytes(input.as_bytes(), edit);
+        let lexbuf = LexerState {
+            text: Some(input),
+            ..lexbuf
+        };
+        (lexbuf, edit)
+    }
+
+    /// Create a lexer state over the edited raw bytes.
+    ///
+    /// See [`edit`](Self::edit).
+    pub fn edit_bytes<'b>(&self, input: &'b [u8], edit: &Edit) -> (LexerState<'b>, Edit) {
+        let lexbuf = LexerState {
+            // the mode stacks of the reused cursors
+            mode_stacks: self.mode_stacks.clone(),
+            ..LexerState::from_bytes(input)
+        };
+        let mut edit = edit.clone();
+        let mode_changes = self.mode_changes.borrow();
+        let layouts = self.layouts.borrow();
+        if mode_changes.range(edit.range.start..).next().is_some()
+            || layouts.range(edit.range.start..).next().is_some()
+        {
+            edit.range.end = usize::MAX;
+        }
+        lexbuf
+            .mode_changes
+            .borrow_mut()
+            .extend(mode_changes.range(..edit.range.start));
+        lexbuf.layouts.borrow_mut().extend(
+            layouts
+                .range(..edit.range.start)
+                .map(|(&k, v)| (k, v.clone())),
+        );
+        for (&start, &end) in self.trivia.borrow().iter() {
+            if end < edit.range.start {
+                lexbuf.trivia.borrow_mut().insert(start, end);
+            } else if start >= edit.range.end {
+                let (start, end) = (edit.shift(start), edit.shift(end));
+                lexbuf.trivia.borrow_mut().insert(start, end);
+            }
+        }
+        (lexbuf, edit)
+    }
+
     /// Run the lexer against the given regex.
     pub fn run(&mut self, regex: &Regex) -> Option<PatternID> {
+        self.run_with(regex as *const Regex as *const (), |input| {
+            // the regex may have looked at any part of the input, e.g. to find
+            // the longest match, so all of it counts as examined
+            (regex.search_half(input), input.haystack().len() + 1)
+        })
+    }
+
+    /// Run the lexer against the given DFA.
+    ///
+    /// The DFA must support anchored searches.
+    pub fn run_dfa<A: Automaton>(&mut self, dfa: &A) -> Option<PatternID> {
+        self.run_with(dfa as *const A as *const (), |input| search_dfa(dfa, input))
+    }
+
+    /// Run the lexer with the given search function, memoized by the identity
+    /// of the regex it searches for.
+    ///
+    /// The search function also returns the extent of the input it examined.
+    fn run_with(
+        &mut self,
+        regex: *const (),
+        search: impl FnOnce(&Input) -> (Option<HalfMatch>, usize),
+    ) -> Option<PatternID> {
         let cursor = self.cursor();
         if let Some(((pattern, re), end)) = self.memo.get(&cursor) {
-            if std::ptr::addr_eq(re, regex) {
-                self.advance_to_cursor(end);
+            if re == regex {
+                self.examine(self.memo.extent(&cursor).unwrap_or(0));
+                self.start = end.start;
+                self.cursor = end.cursor;
+                self.layout = end.layout;
+                self.pattern = Some((pattern, regex));
+                return Some(pattern);
+            }
+        }
+        if self.layout > 0 {
+            // emit the pending layout tokens at this line break, by running
+            // the same rule again on an empty lexeme
+            let run = self.current_layout();
+            if let Some((pattern, _)) = run
+                .ok()
+                .filter(|run| self.layout < run.tokens.len())
+                .and_then(|run| run.pattern)
+                .filter(|&(_, re)| re == regex)
+            {
+                self.start = self.cursor;
+                self.layout += 1;
+                self.pattern = Some((pattern, regex));
+                self.memo.insert(cursor, ((pattern, regex), self.cursor()));
                 return Some(pattern);
             }
         }
+        if self.spans.is_some() {
+            // pre-lexed tokens have no source text to search
+            return None;
+        }
         let input = Input::new(self.input)
             .range(self.cursor..)
             .anchored(Anchored::Yes);
-        let end = regex.search_half(&input)?;
+        let (end, examined) = search(&input);
+        self.examine(examined);
+        let end = end?;
+        if self.layout > 0 && end.offset() == self.cursor {
+            // an empty match would start the same layout over again, e.g. when
+            // the layout rule matches the end of input
+            return None;
+        }
         self.start = self.cursor;
         self.cursor = end.offset();
+        self.layout = 0;
         let pattern = end.pattern();
-        
-        self.memo.insert(cursor, ((pattern, regex), self.cursor()));
+        self.pattern = Some((pattern, regex));
+
+        self.memo
+            .insert_with_extent(cursor, ((pattern, regex), self.cursor()), examined);
         Some(pattern)
     }
 
+    /// Record that the input has been examined up to the given position,
+    /// exclusive.
+    fn examine(&mut self, extent: usize) {
+        self.examined = self.examined.max(extent);
+    }
+
+    /// Take the extent of the input examined by the lexer so far, resetting
+    /// it.
+    pub(crate) fn take_examined(&mut self) -> usize {
+        std::mem::take(&mut self.examined)
+    }
+
     /// Get the lexeme of the current token.
-    pub fn lexeme(&self) -> &'a str {
-        &self.input[self.start..self.cursor]
+    ///
+    /// # Errors
+    /// Returns [`LexErrorKind::UnexpectedByte`] at the first invalid byte if the
+    /// input is made of bytes, and the lexeme is not valid UTF-8. Use
+    /// [`lexeme_bytes`](Self::lexeme_bytes) for such input.
+    ///
+    /// ```
+    /// # use parse_it::{*, lexer::LexErrorKind};
+    /// let mut lexbuf = LexerState::from_bytes(b"a\xff");
+    /// ByteLexer.next(&mut lexbuf).unwrap();
+    /// assert_eq!(lexbuf.lexeme().unwrap(), "a");
+    /// ByteLexer.next(&mut lexbuf).unwrap();
+    /// assert_eq!(lexbuf.lexeme().unwrap_err().kind, LexErrorKind::UnexpectedByte(0xff));
+    /// assert_eq!(lexbuf.lexeme_bytes(), b"\xff");
+    /// ```
+    pub fn lexeme(&self) -> Result<&'a str, LexError> {
+        match self.text {
+            Some(text) => Ok(&text[self.start..self.cursor]),
+            None => from_utf8(self.lexeme_bytes(), self.start),
+        }
+    }
+
+    /// Get the lexeme of the current token as bytes.
+    pub fn lexeme_bytes(&self) -> &'a [u8] {
+        match self.spans {
+            Some(_) => &[],
+            None => &self.input[self.start..self.cursor],
+        }
     }
 
     /// Get the current cursor position.
@@ -87,51 +628,801 @@ impl<'a> LexerState<'a> {
         Cursor {
             start: self.start,
             cursor: self.cursor,
+            layout: self.layout,
+            modes: self.modes,
         }
     }
 
     /// Get the span of the current token.
     pub fn span(&self) -> Span {
-        Span {
-            start: self.start,
-            end: self.cursor,
+        if self.cursor <= self.start {
+            // the empty span is at the start of the next token
+            self.pull_spans(self.cursor + 1);
+        }
+        let spans = self.spans.as_ref().map(|spans| spans.borrow());
+        match spans {
+            Some(spans) if self.cursor > self.start => Span {
+                start: spans[self.start].start,
+                end: spans[self.cursor - 1].end,
+            },
+            Some(spans) => {
+                let pos = match spans.get(self.cursor) {
+                    Some(span) => span.start,
+                    None => spans.last().map_or(0, |span| span.end),
+                };
+                Span {
+                    start: pos,
+                    end: pos,
+                }
+            }
+            None => Span {
+                start: self.start,
+                end: self.cursor,
+            },
         }
     }
 
+    /// Extend the current token to start from the given position.
+    ///
+    /// A token may be made of several lexemes, e.g. when a lexer action runs a
+    /// sub-lexer. This makes the span of the token cover all of them.
+    pub fn extend_from(&mut self, start: usize) {
+        self.start = self.start.min(start);
+    }
+
     /// Check if the lexer is at the end of the input.
     pub fn is_empty(&self) -> bool {
-        self.cursor >= self.input.len()
+        self.pull_spans(self.cursor + 1);
+        self.cursor >= self.len() && !self.has_pending_layout()
+    }
+
+    /// The length of the input, in bytes or in pre-lexed tokens.
+    fn len(&self) -> usize {
+        self.spans
+            .as_ref()
+            .map_or(self.input.len(), |spans| spans.borrow().len())
+    }
+
+    /// Consume the next character as a lexeme of its own.
+    ///
+    /// Returns `None` at the end of input, or if the next byte does not start a
+    /// valid UTF-8 sequence.
+    pub fn advance_char(&mut self) -> Option<char> {
+        let ch = self.next_char()?.ok()?;
+        self.start = self.cursor;
+        self.cursor += ch.len_utf8();
+        self.layout = 0;
+        Some(ch)
+    }
+
+    /// Consume the next pre-lexed token, and get its index, or `None` at the
+    /// end of the tokens.
+    ///
+    /// See [`from_spans`](Self::from_spans).
+    ///
+    /// # Errors
+    /// Returns [`LexErrorKind::MissingToken`] if the lexer state was not
+    /// created over pre-lexed tokens.
+    pub fn advance_token(&mut self) -> Result<Option<usize>, LexError> {
+        if self.spans.is_none() {
+            return Err(self.missing_token());
+        }
+        let index = self.cursor;
+        self.pull_spans(index + 1);
+        if index >= self.len() {
+            return Ok(None);
+        }
+        self.start = index;
+        self.cursor = index + 1;
+        self.layout = 0;
+        Ok(Some(index))
+    }
+
+    /// Report that the current pre-lexed token is missing from the lexer.
+    pub fn missing_token(&self) -> LexError {
+        LexError {
+            span: self.span(),
+            kind: LexErrorKind::MissingToken,
+        }
+    }
+
+    /// Consume the next byte as a lexeme of its own.
+    pub fn advance_byte(&mut self) -> Option<u8> {
+        let byte = *self.input.get(self.cursor)?;
+        self.start = self.cursor;
+        self.cursor += 1;
+        self.layout = 0;
+        Some(byte)
+    }
+
+    /// Decode the character at the cursor, or get the byte there if it does
+    /// not start a valid UTF-8 sequence.
+    fn next_char(&self) -> Option<Result<char, u8>> {
+        let rest = self.input.get(self.cursor..)?;
+        let first = *rest.first()?;
+        let prefix = &rest[..rest.len().min(4)];
+        let valid = match std::str::from_utf8(prefix) {
+            Ok(valid) => valid,
+            Err(e) => std::str::from_utf8(&prefix[..e.valid_up_to()]).unwrap(),
+        };
+        Some(valid.chars().next().ok_or(first))
+    }
+
+    /// Report that no lexer rule matches at the current position.
+    pub fn unexpected(&self) -> LexError {
+        match self.next_char() {
+            Some(Ok(ch)) => LexError {
+                span: Span {
+                    start: self.cursor,
+                    end: self.cursor + ch.len_utf8(),
+                },
+                kind: LexErrorKind::UnexpectedChar(ch),
+            },
+            Some(Err(byte)) => LexError {
+                span: Span {
+                    start: self.cursor,
+                    end: self.cursor + 1,
+                },
+                kind: LexErrorKind::UnexpectedByte(byte),
+            },
+            None => LexError {
+                span: Span {
+                    start: self.cursor,
+                    end: self.cursor,
+                },
+                kind: LexErrorKind::EndOfInput,
+            },
+        }
+    }
+
+    /// Match the current lexeme against the given regex, and get its capture
+    /// groups, indexed by group number. Groups that do not participate in the
+    /// match are `None`.
+    ///
+    /// The regex must match the whole lexeme, e.g. by ending with `\z`. Lexer
+    /// rules with named capture groups use this to bind the groups in their
+    /// actions:
+    ///
+    /// ```
+    /// use parse_it::LexerState;
+    ///
+    /// parse_it::parse_it! {
+    ///     #[lexer]
+    ///     mod lex {
+    ///         pub Initial -> char {
+    ///             r"\\u(?<hex>[0-9a-fA-F]{4})" => char::from_u32(u32::from_str_radix(hex, 16).unwrap()).unwrap(),
+    ///             r"(?<sign>-)?(?<digit>[0-9])" => match sign {
+    ///                 Some(_) => '-',
+    ///                 None => digit.chars().next().unwrap(),
+    ///             },
+    ///         }
+    ///     }
+    /// }
+    ///
+    /// let mut lexbuf = LexerState::new(r"\u0041-17");
+    /// let chars = lexbuf.tokens(&lex::Initial).map(|t| t.unwrap().0).collect::<String>();
+    /// assert_eq!(chars, "A-7");
+    /// ```
+    pub fn captures(&self, regex: &Regex) -> Result<Vec<Option<&'a str>>, LexError> {
+        let input = Input::new(&self.input[..self.cursor])
+            .range(self.start..)
+            .anchored(Anchored::Yes);
+        let mut captures = regex.create_captures();
+        regex.search_captures(&input, &mut captures);
+        (0..captures.group_len())
+            .map(|group| {
+                captures
+                    .get_group(group)
+                    .map(|span| {
+                        self.slice(Span {
+                            start: span.start,
+                            end: span.end,
+                        })
+                    })
+                    .transpose()
+            })
+            .collect()
+    }
+
+    /// Extend the current token, which is an opening delimiter, to the matching
+    /// closing delimiter.
+    ///
+    /// The `delimiters` regex must have the closing delimiter as its first
+    /// pattern and the opening delimiter as its second, neither of them matching
+    /// the empty string. Opening delimiters inside the token nest, so that
+    /// `/* a /* b */ c */` is a single comment.
+    ///
+    /// In `#[lexer]` modules, write the rule as `open .. close` instead.
+    ///
+    /// # Errors
+    /// Returns [`LexErrorKind::UnexpectedEof`] at the opening delimiter if the
+    /// input ends before it is closed.
+    ///
+    /// ```
+    /// use parse_it::{LexerState, lexer::{LexErrorKind, Span}};
+    ///
+    /// parse_it::parse_it! {
+    ///     #[lexer]
+    ///     mod lex {
+    ///         pub Initial -> &'lex str {
+    ///             r"/\*" .. r"\*/" => self,
+    ///             r"\s+" => continue,
+    ///             r"\w+" => self,
+    ///         }
+    ///     }
+    /// }
+    ///
+    /// let mut lexbuf = LexerState::new("a /* b /* c */ d */ e");
+    /// let tokens = lexbuf.tokens(&lex::Initial).map(|t| t.unwrap().0).collect::<Vec<_>>();
+    /// assert_eq!(tokens, ["a", "/* b /* c */ d */", "e"]);
+    ///
+    /// let mut lexbuf = LexerState::new("a /* b /* c */ d");
+    /// let error = lexbuf.tokens(&lex::Initial).find_map(Result::err).unwrap();
+    /// assert_eq!(error.kind, LexErrorKind::UnexpectedEof);
+    /// assert_eq!(error.span, Span { start: 2, end: 4 });
+    /// ```
+    pub fn nested(&mut self, delimiters: &Regex) -> Result<(), LexError> {
+        let opening = self.span();
+        let mut depth = 1;
+        while depth > 0 {
+            let input = Input::new(self.input).range(self.cursor..);
+            let Some(delimiter) = delimiters.search(&input) else {
+                self.cursor = self.input.len();
+                self.examine(self.input.len() + 1);
+                return Err(LexError {
+                    span: opening,
+                    kind: LexErrorKind::UnexpectedEof,
+                });
+            };
+            self.cursor = delimiter.end();
+            self.examine(self.cursor + 1);
+            match delimiter.pattern().as_usize() {
+                0 => depth -= 1,
+                _ => depth += 1,
+            }
+        }
+        Ok(())
+    }
+
+    /// Get the source text of the given span.
+    ///
+    /// # Errors
+    /// Returns [`LexErrorKind::UnexpectedByte`] at the first invalid byte if the
+    /// input is made of bytes, and the span is not valid UTF-8. Use
+    /// [`slice_bytes`](Self::slice_bytes) for such input.
+    pub fn slice(&self, span: Span) -> Result<&'a str, LexError> {
+        match self.text {
+            Some(text) => Ok(&text[span.start..span.end]),
+            None => from_utf8(self.slice_bytes(span), span.start),
+        }
+    }
+
+    /// Get the source bytes of the given span.
+    pub fn slice_bytes(&self, span: Span) -> &'a [u8] {
+        &self.input[span.start..span.end]
+    }
+
+    /// Replace the input, with an extension of it.
+    pub(crate) fn with_input(self, input: &'a [u8]) -> Self {
+        Self { input, ..self }
+    }
+
+    /// Get all the source bytes.
+    pub(crate) fn source_bytes(&self) -> &'a [u8] {
+        self.input
+    }
+
+    /// Record the current lexeme as trivia.
+    ///
+    /// Trivia, such as whitespace and comments, are skipped by the parser, but
+    /// can be retrieved from the tokens around them via
+    /// [`leading_trivia`](Self::leading_trivia) and
+    /// [`trailing_trivia`](Self::trailing_trivia).
+    pub fn add_trivia(&mut self) {
+        self.trivia.borrow_mut().insert(self.start, self.cursor);
+    }
+
+    /// Get the leading trivia of the token at the given span.
+    ///
+    /// These are the trivia between the previous token and this one, except
+    /// the trailing trivia of the previous token.
+    ///
+    /// ```
+    /// use parse_it::LexerState;
+    ///
+    /// parse_it::parse_it! {
+    ///     #[lexer]
+    ///     mod lex {
+    ///         pub Initial -> &'lex str {
+    ///             r"[ \t]+" => #[trivia],
+    ///             r"\n" => #[trivia],
+    ///             r"//[^\n]*" => #[trivia],
+    ///             r"\w+" => self,
+    ///         }
+    ///     }
+    /// }
+    ///
+    /// let mut lexbuf = LexerState::new("a // b\n  c");
+    /// let tokens = lexbuf.tokens(&lex::Initial).collect::<Result<Vec<_>, _>>().unwrap();
+    /// let (a, c) = (tokens[0].1, tokens[1].1);
+    ///
+    /// let trailing = lexbuf.trailing_trivia(a);
+    /// assert_eq!(trailing.iter().map(|t| t.text).collect::<Vec<_>>(), [" ", "// b", "\n"]);
+    /// let leading = lexbuf.leading_trivia(c);
+    /// assert_eq!(leading.iter().map(|t| t.text).collect::<Vec<_>>(), ["  "]);
+    /// ```
+    pub fn leading_trivia(&self, span: Span) -> Vec<Trivia<'a>> {
+        let trivia = self.trivia.borrow();
+        let mut chain = vec![];
+        let mut end = span.start;
+        while let Some((&start, _)) = trivia.range(..end).next_back().filter(|(_, &e)| e == end) {
+            chain.push(Span { start, end });
+            end = start;
+        }
+        chain.reverse();
+        // trivia right after the previous token belong to it
+        let skip = if end == 0 {
+            0
+        } else {
+            self.trailing_len(&chain)
+        };
+        chain[skip..]
+            .iter()
+            .map(|&span| self.to_trivia(span))
+            .collect()
+    }
+
+    /// Get the trailing trivia of the token at the given span.
+    ///
+    /// These are the trivia after the token, up to and including the first one
+    /// that contains a line break. Only the trivia that have been lexed so far
+    /// are available.
+    pub fn trailing_trivia(&self, span: Span) -> Vec<Trivia<'a>> {
+        let trivia = self.trivia.borrow();
+        let mut chain = vec![];
+        let mut start = span.end;
+        while let Some(&end) = trivia.get(&start) {
+            chain.push(Span { start, end });
+            start = end;
+        }
+        chain.truncate(self.trailing_len(&chain));
+        chain.iter().map(|&span| self.to_trivia(span)).collect()
+    }
+
+    fn trailing_len(&self, chain: &[Span]) -> usize {
+        chain
+            .iter()
+            .position(|&span| self.slice_bytes(span).contains(&b'\n'))
+            .map_or(chain.len(), |i| i + 1)
+    }
+
+    fn to_trivia(&self, span: Span) -> Trivia<'a> {
+        Trivia {
+            span,
+            text: self.slice(span).unwrap_or_default(),
+        }
+    }
+
+    /// Iterate over the tokens produced by the given lexer, along with their spans.
+    ///
+    /// The iteration stops after the first lexical error.
+    ///
+    /// ```
+    /// # use parse_it::{*, lexer::Span};
+    /// let mut lexbuf = LexerState::new("ab");
+    /// let tokens = lexbuf.tokens(&CharLexer).collect::<Result<Vec<_>, _>>().unwrap();
+    /// assert_eq!(tokens, [('a', Span { start: 0, end: 1 }), ('b', Span { start: 1, end: 2 })]);
+    /// ```
+    pub fn tokens<'b, L: LexIt>(&'b mut self, lexer: &'b L) -> Tokens<'a, 'b, L> {
+        Tokens {
+            lexer,
+            lexbuf: self,
+            done: false,
+        }
     }
 
     /// Advance the lexer to the given cursor position.
+    ///
+    /// The mode stack is restored to the one of the cursor.
     pub fn advance_to_cursor(&mut self, cursor: Cursor) {
         self.start = cursor.start;
         self.cursor = cursor.cursor;
+        self.layout = cursor.layout;
+        self.modes = cursor.modes;
+    }
+
+    /// Get the current lexer mode, or `None` if no mode has been pushed.
+    pub fn mode(&self) -> Option<usize> {
+        let stacks = self.mode_stacks.borrow();
+        stacks.top(self.modes).map(|(_, mode)| mode)
+    }
+
+    /// Push a lexer mode onto the mode stack.
+    ///
+    /// The mode takes effect from the end of the current token.
+    pub fn push_mode(&mut self, mode: usize) {
+        self.modes = self.mode_stacks.borrow_mut().push(self.modes, mode);
+        self.mode_changes.borrow_mut().insert(self.cursor);
+    }
+
+    /// Pop the current lexer mode from the mode stack.
+    pub fn pop_mode(&mut self) -> Option<usize> {
+        let (below, mode) = self.mode_stacks.borrow().top(self.modes)?;
+        self.modes = below;
+        self.mode_changes.borrow_mut().insert(self.cursor);
+        Some(mode)
+    }
+
+    /// Apply the offside rule at a line break.
+    ///
+    /// This is meant to be called from the action of a rule matching a line
+    /// break and the indentation of the next line, e.g. `r"(\n[ \t]*)+"`. The
+    /// indentation is compared against the stack of enclosing indentation
+    /// levels, and the rule produces a [`Layout::Newline`], followed by either
+    /// a [`Layout::Indent`] if the line is indented further, or a
+    /// [`Layout::Dedent`] for every level it closes. The end of input closes
+    /// all levels; add `|\z` to the rule to close them even without a trailing
+    /// line break.
+    ///
+    /// Tabs advance the indentation to the next multiple of `tab_width`.
+    ///
+    /// In `#[lexer]` modules, use `layout!(tab_width)` instead.
+    ///
+    /// # Errors
+    /// Returns [`LexErrorKind::InconsistentDedent`] if the line is dedented to
+    /// a width that matches no enclosing level.
+    ///
+    /// # Panics
+    /// Panics if `tab_width` is zero.
+    ///
+    /// ```
+    /// use parse_it::{LexerState, lexer::{Layout, LexErrorKind}};
+    ///
+    /// parse_it::parse_it! {
+    ///     #[lexer]
+    ///     mod lex {
+    ///         use parse_it::lexer::Layout;
+    ///
+    ///         pub Initial -> &'lex str {
+    ///             r"(\n[ \t]*)+" => match layout!(8) {
+    ///                 Layout::Newline => "NEWLINE",
+    ///                 Layout::Indent => "INDENT",
+    ///                 Layout::Dedent => "DEDENT",
+    ///             },
+    ///             r" +" => continue,
+    ///             r"[^\s]+" => self,
+    ///         }
+    ///     }
+    /// }
+    ///
+    /// let mut lexbuf = LexerState::new("if a:\n    if b:\n\tc\nd\n");
+    /// let tokens = lexbuf.tokens(&lex::Initial).map(|t| t.unwrap().0).collect::<Vec<_>>();
+    /// assert_eq!(tokens.join(" "), "if a: NEWLINE INDENT if b: NEWLINE INDENT c NEWLINE DEDENT DEDENT d NEWLINE");
+    ///
+    /// let mut lexbuf = LexerState::new("a\n    b\n  c");
+    /// let error = lexbuf.tokens(&lex::Initial).find_map(Result::err).unwrap();
+    /// assert_eq!(error.kind, LexErrorKind::InconsistentDedent);
+    /// ```
+    pub fn layout(&mut self, tab_width: usize) -> Result<Layout, LexError> {
+        assert!(tab_width > 0, "the tab width must be positive");
+        self.tab_width = tab_width;
+        let run = self.current_layout()?;
+        if self.layout == 0 {
+            self.layout = 1;
+        }
+        Ok(run.tokens[self.layout - 1])
+    }
+
+    /// Get the layout tokens at the current line break, computing them if this
+    /// is the first time the line break is lexed, or if they were lost, e.g.
+    /// for a cursor restored on another lexer state.
+    fn current_layout(&self) -> Result<LayoutRun, LexError> {
+        if let Some(run) = self.layouts.borrow().get(&self.cursor) {
+            return Ok(run.clone());
+        }
+        let run = self.layout_run()?;
+        self.layouts.borrow_mut().insert(self.cursor, run.clone());
+        Ok(run)
+    }
+
+    fn layout_run(&self) -> Result<LayoutRun, LexError> {
+        let tab_width = self.tab_width;
+        let line = self.input[..self.cursor]
+            .rsplit(|&b| b == b'\n')
+            .next()
+            .unwrap_or_default();
+        let width = if self.cursor >= self.input.len() {
+            0
+        } else {
+            line.iter().fold(0, |width, &b| match b {
+                b'\t' => (width / tab_width + 1) * tab_width,
+                // continuation bytes of a multi-byte character
+                0x80..0xc0 => width,
+                _ => width + 1,
+            })
+        };
+
+        let layouts = self.layouts.borrow();
+        let mut indents = match layouts.range(..self.cursor).next_back() {
+            Some((_, run)) => run.indents.clone(),
+            None => Default::default(),
+        };
+        let mut tokens = vec![Layout::Newline];
+        if width > indents.last().copied().unwrap_or(0) {
+            Rc::make_mut(&mut indents).push(width);
+            tokens.push(Layout::Indent);
+        } else {
+            while indents.last().is_some_and(|&level| level > width) {
+                Rc::make_mut(&mut indents).pop();
+                tokens.push(Layout::Dedent);
+            }
+            if indents.last().copied().unwrap_or(0) != width {
+                return Err(LexError {
+                    span: Span {
+                        start: self.cursor - line.len(),
+                        end: self.cursor,
+                    },
+                    kind: LexErrorKind::InconsistentDedent,
+                });
+            }
+        }
+
+        Ok(LayoutRun {
+            pattern: self.pattern,
+            tokens,
+            indents,
+        })
+    }
+
+    /// Whether some layout tokens at the current line break are yet to be
+    /// emitted.
+    fn has_pending_layout(&self) -> bool {
+        self.layout > 0
+            && self
+                .current_layout()
+                .is_ok_and(|run| self.layout < run.tokens.len())
+    }
+}
+
+/// A synthetic token produced by the offside rule.
+///
+/// See [`LexerState::layout`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Layout {
+    /// The end of a logical line.
+    Newline,
+    /// The start of an indented block.
+    Indent,
+    /// The end of an indented block.
+    Dedent,
+}
+
+/// Convert the source bytes starting at the given position to a string, or
+/// report the first byte that is not valid UTF-8.
+fn from_utf8(bytes: &[u8], start: usize) -> Result<&str, LexError> {
+    std::str::from_utf8(bytes).map_err(|error| {
+        let pos = error.valid_up_to();
+        LexError {
+            span: Span {
+                start: start + pos,
+                end: start + pos + 1,
+            },
+            kind: LexErrorKind::UnexpectedByte(bytes[pos]),
+        }
+    })
+}
+
+/// A piece of trivia, such as whitespace or a comment.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Trivia<'a> {
+    /// The span of the trivia.
+    pub span: Span,
+    /// The source text of the trivia, or an empty string if the input is made
+    /// of bytes and the trivia are not valid UTF-8.
+    pub text: &'a str,
+}
+
+/// An iterator over the tokens of a lexer and their spans.
+///
+/// This is created by [`LexerState::tokens`].
+pub struct Tokens<'a, 'b, L> {
+    lexer: &'b L,
+    lexbuf: &'b mut LexerState<'a>,
+    done: bool,
+}
+
+impl<'a, L: LexIt> Iterator for Tokens<'a, '_, L> {
+    type Item = Result<(L::Token<'a>, Span), LexError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        let result = self.lexer.next_spanned(self.lexbuf).transpose();
+        self.done = !matches!(result, Some(Ok(_)));
+        result
     }
 }
 
 /// A lexer for a single character.
-#[derive(Clone)]
+#[derive(Clone, Default)]
 pub struct CharLexer;
 
 impl LexIt for CharLexer {
     type Token<'a> = char;
 
-    fn new() -> Self {
-        Self
+    fn next<'a>(&self, lexbuf: &mut LexerState<'a>) -> Result<Option<Self::Token<'a>>, LexError> {
+        match lexbuf.advance_char() {
+            Some(ch) => Ok(Some(ch)),
+            None => end_of_stream(Err(lexbuf.unexpected())),
+        }
     }
+}
 
-    fn next<'a>(&self, lexbuf: &mut LexerState<'a>) -> Option<Self::Token<'a>> {
-        thread_local! {
-            static REGEX: Regex = Regex::new(r".").unwrap();
+/// A lexer for a single byte.
+///
+/// This is the counterpart of [`CharLexer`] for byte input, as created by
+/// [`LexerState::from_bytes`]. Byte literals `b'x'` and `b"abc"` in parser
+/// productions match the bytes of the input.
+///
+/// ```
+/// # use parse_it::{*, lexer::Span};
+/// let mut lexbuf = LexerState::from_bytes(b"\x00\xff");
+/// let tokens = lexbuf.tokens(&ByteLexer).collect::<Result<Vec<_>, _>>().unwrap();
+/// assert_eq!(tokens, [(0x00, Span { start: 0, end: 1 }), (0xff, Span { start: 1, end: 2 })]);
+/// ```
+#[derive(Clone, Default)]
+pub struct ByteLexer;
+
+impl LexIt for ByteLexer {
+    type Token<'a> = u8;
+
+    fn next<'a>(&self, lexbuf: &mut LexerState<'a>) -> Result<Option<Self::Token<'a>>, LexError> {
+        match lexbuf.advance_byte() {
+            Some(byte) => Ok(Some(byte)),
+            None => end_of_stream(Err(lexbuf.unexpected())),
         }
-        REGEX.with(|regex| {
-            if lexbuf.run(regex).is_some() {
-                let lexeme = lexbuf.lexeme();
-                Some(lexeme.chars().next().unwrap())
-            } else {
-                None
+    }
+}
+
+/// A lexer over pre-lexed tokens, e.g. from `proc_macro2`, another lexer or a
+/// cached token file.
+///
+/// Set `type Lexer = SliceLexer<Token>` in a `#[parser]` module to match the
+/// tokens with pattern and typed terminals, and parse them with
+/// [`ParseIt::parse_tokens`](crate::ParseIt::parse_tokens). Spans of parsed
+/// tokens and errors are the spans of the tokens. Literal terminals never
+/// match, as there is no source text.
+///
+/// The lexer can only be created over its tokens, and lexes the lexer state
+/// created by [`lexer_state`](Self::lexer_state). On another lexer state, it
+/// reports [`LexErrorKind::MissingToken`].
+///
+/// ```
+/// # use parse_it::{*, lexer::{LexErrorKind, SliceLexer, Span}};
+/// let lexer = SliceLexer::from_tokens(&[('x', Span { start: 0, end: 1 })]);
+/// let mut lexbuf = lexer.lexer_state();
+/// assert_eq!(lexer.next(&mut lexbuf).unwrap(), Some('x'));
+/// assert_eq!(lexer.next(&mut lexbuf).unwrap(), None);
+///
+/// let mut lexbuf = LexerState::new("x");
+/// assert_eq!(lexer.next(&mut lexbuf).unwrap_err().kind, LexErrorKind::MissingToken);
+/// ```
+pub struct SliceLexer<T> {
+    tokens: Rc<[(T, Span)]>,
+}
+
+impl<T: Clone> SliceLexer<T> {
+    /// Create a lexer over the given tokens and their spans.
+    pub fn from_tokens(tokens: &[(T, Span)]) -> Self {
+        Self {
+            tokens: tokens.into(),
+        }
+    }
+
+    /// Create a lexer state for this lexer, over the spans of its tokens.
+    pub fn lexer_state<'a>(&self) -> LexerState<'a> {
+        LexerState::from_spans(self.tokens.iter().map(|&(_, span)| span))
+    }
+}
+
+impl<T> Clone for SliceLexer<T> {
+    fn clone(&self) -> Self {
+        Self {
+            tokens: self.tokens.clone(),
+        }
+    }
+}
+
+impl<T: Clone> LexIt for SliceLexer<T> {
+    type Token<'a> = T;
+
+    fn next<'a>(&self, lexbuf: &mut LexerState<'a>) -> Result<Option<Self::Token<'a>>, LexError> {
+        let Some(index) = lexbuf.advance_token()? else {
+            return Ok(None);
+        };
+        match self.tokens.get(index) {
+            Some((token, _)) => Ok(Some(token.clone())),
+            None => Err(lexbuf.missing_token()),
+        }
+    }
+}
+
+/// An adapter for external lexers, e.g. [logos], which produce tokens and
+/// their byte ranges through an iterator.
+///
+/// Tokens are pulled from the iterator on demand and buffered, so that forks
+/// of the parser state can revisit them. Set `type Lexer = IterLexer<Token>`
+/// in a `#[parser]` module, and parse with
+/// [`ParseIt::parse_token_iter`](crate::ParseIt::parse_token_iter). The
+/// iterator is held by the lexer state, not named by the lexer type, so it
+/// may borrow a source text that lives no longer than the parse. As with
+/// [`SliceLexer`], tokens are matched by pattern and typed terminals, and the
+/// lexer only lexes the lexer state it is created with.
+///
+/// ```
+/// # use parse_it::{*, lexer::{IterLexer, LexErrorKind}};
+/// let src = String::from("let x");
+/// let words = src.split(' ').scan(0, |start, word| {
+///     let range = *start..*start + word.len();
+///     *start = range.end + 1;
+///     Some((word.len(), range))
+/// });
+/// let (lexer, mut lexbuf) = IterLexer::from_tokens(words);
+/// assert_eq!(lexer.next(&mut lexbuf).unwrap(), Some(3));
+/// assert_eq!(lexbuf.span(), Span { start: 0, end: 3 });
+/// assert_eq!(lexer.next(&mut lexbuf).unwrap(), Some(1));
+/// assert_eq!(lexer.next(&mut lexbuf).unwrap(), None);
+///
+/// let mut lexbuf = LexerState::new("x");
+/// assert_eq!(lexer.next(&mut lexbuf).unwrap_err().kind, LexErrorKind::MissingToken);
+/// ```
+///
+/// [logos]: https://docs.rs/logos
+pub struct IterLexer<T> {
+    tokens: Rc<RefCell<Vec<T>>>,
+}
+
+impl<T> IterLexer<T> {
+    /// Create a lexer over the given token iterator, along with the lexer
+    /// state to lex.
+    pub fn from_tokens<'a, I>(iter: I) -> (Self, LexerState<'a>)
+    where
+        T: 'a,
+        I: IntoIterator<Item = (T, Range<usize>)>,
+        I::IntoIter: 'a,
+    {
+        let tokens = Rc::new(RefCell::new(vec![]));
+        let buffer = tokens.clone();
+        let spans = iter.into_iter().map(move |(token, range)| {
+            buffer.borrow_mut().push(token);
+            Span {
+                start: range.start,
+                end: range.end,
             }
-        })
+        });
+        (Self { tokens }, LexerState::from_span_iter(spans))
+    }
+}
+
+impl<T> Clone for IterLexer<T> {
+    fn clone(&self) -> Self {
+        Self {
+            tokens: self.tokens.clone(),
+        }
+    }
+}
+
+impl<T: Clone> LexIt for IterLexer<T> {
+    type Token<'a> = T;
+
+    fn next<'a>(&self, lexbuf: &mut LexerState<'a>) -> Result<Option<Self::Token<'a>>, LexError> {
+        let Some(index) = lexbuf.advance_token()? else {
+            return Ok(None);
+        };
+        match self.tokens.borrow().get(index) {
+            Some(token) => Ok(Some(token.clone())),
+            None => Err(lexbuf.missing_token()),
+        }
     }
 }
@@ -48,19 +48,156 @@
 //!     println!("{:?}", instrs);
 //! }
 //! ```
+//!
+//! ## Actions
+//!
+//! The action of a production refers to the captured values by name, or to
+//! all of them as `self`. A pattern terminal binds the span of its token
+//! after an `@`, as in `name @ span`, while a tuple pattern such as `(a, b)`
+//! matches tuple tokens. The [`ParserState`] being parsed is available as
+//! `state!()`, e.g. to get the trivia around a token:
+//!
+//! ```rust
+//! use parse_it::{ParseIt, parse_it};
+//!
+//! parse_it! {
+//!     #[lexer]
+//!     mod lex {
+//!         pub Initial -> &'lex str {
+//!             r"\s+" => #[trivia],
+//!             r"//[^\n]*" => #[trivia],
+//!             r"\w+" => self,
+//!         }
+//!     }
+//!
+//!     #[parser]
+//!     mod parse {
+//!         type Lexer = super::lex::Initial;
+//!
+//!         pub Item -> (String, Vec<String>) {
+//!             name @ span => {
+//!                 let comments = state!().leading_trivia(span).into_iter()
+//!                     .filter(|trivia| trivia.text.starts_with("//"))
+//!                     .map(|trivia| trivia.text.to_string())
+//!                     .collect();
+//!                 (name.to_string(), comments)
+//!             }
+//!         }
+//!     }
+//! }
+//!
+//! fn main() {
+//!     let parser = parse::Item::default();
+//!     let (name, comments) = parser.parse("// the answer\n  answer").unwrap();
+//!     assert_eq!(name, "answer");
+//!     assert_eq!(comments, ["// the answer"]);
+//! }
+//! ```
+//!
+//! ## Generated types
+//!
+//! In a parser module marked with `#[parse_it(ast)]`, the parsers without a
+//! type get one generated from their rules, with the span they parsed, in a
+//! module named `ast` next to the parsers. The module is named otherwise with
+//! `#[parse_it(ast = "name")]`, e.g. if the parser module has an item named
+//! `ast` already:
+//!
+//! ```rust
+//! use parse_it::{ParseIt, parse_it};
+//!
+//! parse_it! {
+//!     #[parser]
+//!     #[parse_it(ast = "syntax")]
+//!     mod parse {
+//!         type Lexer = parse_it::CharLexer;
+//!
+//!         mod ast {}
+//!
+//!         pub Pair {
+//!             first:<char> second:<char>,
+//!         }
+//!     }
+//! }
+//!
+//! fn main() {
+//!     let pair = parse::Pair::default().parse("ab").unwrap();
+//!     assert_eq!((pair.first, pair.second), ('a', 'b'));
+//!     assert_eq!((pair.span.start, pair.span.end), (0, 2));
+//! }
+//! ```
+//!
+//! ## Visitors
+//!
+//! The `ast` module also has the modules `visit`, `visit_mut` and `fold`, with
+//! the traits `Visit`, `VisitMut` and `Fold`. They have a method for the type
+//! of each parser, named after the parser, e.g. `visit_add_expr` for
+//! `AddExpr`, or after the first parser of the type if several parsers share
+//! it. Every method defaults to the function of the same name in the module,
+//! which walks the fields of the value that hold values of parsers: the fields
+//! of generated types, and those of the structs and enums defined in the
+//! parser module. Values of other types have no fields to walk.
+//!
+//! Parser modules marked with `#[parse_it(visit)]` get these modules next to
+//! the parsers, for the types the parsers have of their own:
+//!
+//! ```rust
+//! use parse_it::{ParseIt, parse_it};
+//!
+//! parse_it! {
+//!     #[parser]
+//!     #[parse_it(visit)]
+//!     mod parse {
+//!         type Lexer = parse_it::CharLexer;
+//!
+//!         #[derive(Debug, Clone)]
+//!         pub enum Tree {
+//!             Leaf(char),
+//!             Node(Vec<Tree>),
+//!         }
+//!
+//!         pub Item -> Tree {
+//!             c:Letter => Tree::Leaf(c),
+//!             '(' items:Item* ')' => Tree::Node(items),
+//!         }
+//!
+//!         Letter -> char {
+//!             c @ 'a'..='z' => c,
+//!         }
+//!     }
+//! }
+//!
+//! struct Letters(String);
+//!
+//! impl<'ast> parse::visit::Visit<'ast> for Letters {
+//!     fn visit_letter(&mut self, c: &'ast char) {
+//!         self.0.push(*c);
+//!     }
+//! }
+//!
+//! fn main() {
+//!     use parse::visit::Visit;
+//!
+//!     let tree = parse::Item::default().parse("(a(bc)d)").unwrap();
+//!     let mut letters = Letters(String::new());
+//!     letters.visit_item(&tree);
+//!     assert_eq!(letters.0, "abcd");
+//! }
+//! ```
 #![warn(missing_docs)]
 #![allow(clippy::needless_doctest_main)]
 
+pub mod cst;
 pub mod lexer;
 pub mod memo;
 pub mod parser;
+pub mod stream;
 
 pub use parse_it_macros::parse_it;
 
 pub use crate::{
-    lexer::{CharLexer, Cursor, LexerState},
+    lexer::{ByteLexer, CharLexer, Cursor, LexError, LexerState, Span},
     memo::{left_rec, memorize, Memo},
-    parser::{Error, ParserState},
+    parser::{Error, ErrorKind, ParserState},
 };
 
 /// A lexer.
@@ -68,11 +205,42 @@ pub trait LexIt {
     /// The token type.
     type Token<'a>;
 
+    /// Literal spellings of the token kinds.
+    ///
+    /// Literal terminals in parser productions are matched by token kind when
+    /// the lexer defines its literals, and a literal that is not listed here is
+    /// a compile-time error. Otherwise, they are matched against the lexeme.
+    const LITERALS: &'static [&'static str] = &[];
+
     /// Create a new lexer instance.
-    fn new() -> Self;
+    ///
+    /// Lexers with inputs are constructed with their fields instead, unless
+    /// the inputs have defaults.
+    fn new() -> Self
+    where
+        Self: Default,
+    {
+        Self::default()
+    }
+
+    /// Get the kind of a token, as an index into [`LITERALS`](LexIt::LITERALS).
+    fn kind(_token: &Self::Token<'_>) -> Option<usize> {
+        None
+    }
 
     /// Get the next token from the lexer.
-    fn next<'a>(&self, lexbuf: &mut LexerState<'a>) -> Option<Self::Token<'a>>;
+    ///
+    /// Returns `Ok(None)` at the end of the input.
+    fn next<'a>(&self, lexbuf: &mut LexerState<'a>) -> Result<Option<Self::Token<'a>>, LexError>;
+
+    /// Get the next token from the lexer, along with its span.
+    fn next_spanned<'a>(
+        &self,
+        lexbuf: &mut LexerState<'a>,
+    ) -> Result<Option<(Self::Token<'a>, Span)>, LexError> {
+        let token = self.next(lexbuf)?;
+        Ok(token.map(|token| (token, lexbuf.span())))
+    }
 }
 
 /// A parser.
@@ -89,10 +257,68 @@ pub trait ParseIt {
     ) -> Result<Self::Output, Error>;
 
     /// Parse from a string.
-    fn parse(&self, input: &str) -> Result<Self::Output, Error> {
+    fn parse(&self, input: &str) -> Result<Self::Output, Error>
+    where
+        Self::Lexer: Default,
+    {
         let mut state = ParserState::new(input);
         self.parse_stream(&mut state)
     }
+
+    /// Parse from a string, with the given lexer instance.
+    fn parse_with_lexer(&self, input: &str, lexer: Self::Lexer) -> Result<Self::Output, Error> {
+        let mut state = ParserState::with_lexer(input, lexer);
+        self.parse_stream(&mut state)
+    }
+
+    /// Parse from raw bytes.
+    fn parse_bytes(&self, input: &[u8]) -> Result<Self::Output, Error>
+    where
+        Self::Lexer: Default,
+    {
+        let mut state = ParserState::from_bytes(input);
+        self.parse_stream(&mut state)
+    }
+
+    /// Parse items one at a time from a reader, e.g. the records of a file too
+    /// large to hold in memory.
+    ///
+    /// See [`ReadItems`](stream::ReadItems).
+    fn parse_reader<R: std::io::BufRead>(&self, reader: R) -> stream::ReadItems<'_, Self, R>
+    where
+        Self: Sized,
+        Self::Lexer: Default,
+    {
+        stream::ReadItems::new(self, reader)
+    }
+
+    /// Parse from pre-lexed tokens and their spans, with a [`SliceLexer`].
+    ///
+    /// [`SliceLexer`]: crate::lexer::SliceLexer
+    fn parse_tokens<T: Clone>(&self, tokens: &[(T, Span)]) -> Result<Self::Output, Error>
+    where
+        Self: ParseIt<Lexer = lexer::SliceLexer<T>>,
+    {
+        let mut state = ParserState::from_tokens(tokens);
+        self.parse_stream(&mut state)
+    }
+
+    /// Parse from the tokens of an external lexer and their byte ranges, with
+    /// an [`IterLexer`].
+    ///
+    /// The iterator may borrow the source text, e.g. a `logos::SpannedIter`
+    /// over a `String`.
+    ///
+    /// [`IterLexer`]: crate::lexer::IterLexer
+    fn parse_token_iter<T, I>(&self, tokens: I) -> Result<Self::Output, Error>
+    where
+        T: Clone,
+        I: IntoIterator<Item = (T, std::ops::Range<usize>)>,
+        Self: ParseIt<Lexer = lexer::IterLexer<T>>,
+    {
+        let mut state = ParserState::from_token_iter(tokens);
+        self.parse_stream(&mut state)
+    }
 }
 
 #[doc(hidden)]
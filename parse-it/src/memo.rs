@@ -1,10 +1,17 @@
 //! Memoization and left recursion support.
 
-use std::{cell::RefCell, fmt::Debug, hash::Hash};
+use std::{
+    cell::{Cell, RefCell},
+    fmt::Debug,
+    hash::Hash,
+};
 
 use rustc_hash::FxHashMap;
 
-use crate::{lexer::Cursor, Error, LexIt, ParserState};
+use crate::{
+    lexer::{Cursor, Edit},
+    Error, LexIt, ParserState,
+};
 
 /// Memorization for a parser.
 ///
@@ -13,12 +20,18 @@ use crate::{lexer::Cursor, Error, LexIt, ParserState};
 #[derive(Clone)]
 pub struct Memo<P: Clone + Eq + Hash, T: Clone> {
     map: RefCell<FxHashMap<P, (T, P)>>,
+    extents: RefCell<FxHashMap<P, usize>>,
+    token_starts: RefCell<FxHashMap<P, usize>>,
+    generation: Cell<u64>,
 }
 
 impl<P: Clone + Eq + Hash, T: Clone> Default for Memo<P, T> {
     fn default() -> Self {
         Self {
             map: RefCell::new(FxHashMap::default()),
+            extents: RefCell::new(FxHashMap::default()),
+            token_starts: RefCell::new(FxHashMap::default()),
+            generation: Cell::new(0),
         }
     }
 }
@@ -39,6 +52,106 @@ impl<P: Clone + Eq + Hash, T: Clone> Memo<P, T> {
     pub fn insert(&self, pos: P, value: (T, P)) {
         self.map.borrow_mut().insert(pos, value);
     }
+
+    /// Get the extent of the input examined to parse a memoized value.
+    pub fn extent(&self, pos: &P) -> Option<usize> {
+        self.extents.borrow().get(pos).copied()
+    }
+
+    /// Insert a memoized value, along with the extent of the input examined
+    /// to parse it.
+    pub fn insert_with_extent(&self, pos: P, value: (T, P), extent: usize) {
+        self.extents.borrow_mut().insert(pos.clone(), extent);
+        self.insert(pos, value);
+    }
+
+    /// Get the start of the first token consumed to parse a memoized value.
+    pub fn token_start(&self, pos: &P) -> Option<usize> {
+        self.token_starts.borrow().get(pos).copied()
+    }
+
+    /// Record the start of the first token consumed to parse a memoized value.
+    pub fn insert_token_start(&self, pos: P, start: Option<usize>) {
+        match start {
+            Some(start) => self.token_starts.borrow_mut().insert(pos, start),
+            None => self.token_starts.borrow_mut().remove(&pos),
+        };
+    }
+
+    /// Start memoizing the values of a parse of the parser state of the given
+    /// generation, see [`ParserState::generation`].
+    ///
+    /// Returns the edit to update the memoized values with, if the state is
+    /// an edit of the one they were memoized for. Otherwise, they belong to
+    /// another text, and are to be forgotten.
+    pub fn renew<'e>(&self, generation: u64, edit: Option<&'e (u64, Edit)>) -> Option<&'e Edit> {
+        let previous = self.generation.replace(generation);
+        match edit {
+            Some((edited, edit)) if *edited == previous => Some(edit),
+            _ => None,
+        }
+    }
+
+    /// Forget all memoized values.
+    pub fn clear(&self) {
+        self.map.borrow_mut().clear();
+        self.extents.borrow_mut().clear();
+        self.token_starts.borrow_mut().clear();
+    }
+}
+
+impl<T: Clone> Memo<Cursor, T> {
+    /// Update the memoized values after an edit of the source text.
+    ///
+    /// Values whose parsing examined the edited range are forgotten, and those
+    /// after it are moved to their positions in the edited text. Values
+    /// inserted without an extent are kept only if they are after the edit.
+    ///
+    /// The values themselves are not changed, so positions they hold, such as
+    /// spans, would be stale. Use [`Memo::forget_after`] for such values.
+    pub fn edit(&self, edit: &Edit) {
+        let map = std::mem::take(&mut *self.map.borrow_mut());
+        let extents = std::mem::take(&mut *self.extents.borrow_mut());
+        let token_starts = std::mem::take(&mut *self.token_starts.borrow_mut());
+        for (pos, (value, end)) in map {
+            let extent = extents.get(&pos).copied();
+            let token_start = token_starts.get(&pos).copied();
+            if extent.is_some_and(|extent| extent <= edit.range.start) {
+                self.insert_with_extent(pos, (value, end), extent.unwrap());
+                self.insert_token_start(pos, token_start);
+            } else if pos.is_after(edit) {
+                let value = (value, end.shift(edit));
+                let token_start = token_start.map(|start| edit.shift(start));
+                match extent {
+                    Some(extent) => {
+                        self.insert_with_extent(pos.shift(edit), value, edit.shift(extent))
+                    }
+                    None => self.insert(pos.shift(edit), value),
+                }
+                self.insert_token_start(pos.shift(edit), token_start);
+            }
+        }
+    }
+
+    /// Forget the memoized values whose parsing examined the edited range, or
+    /// anything after it.
+    ///
+    /// Unlike [`Memo::edit`], no value is moved, so this suits values holding
+    /// positions in the source text.
+    pub fn forget_after(&self, edit: &Edit) {
+        let extents = std::mem::take(&mut *self.extents.borrow_mut());
+        let mut map = self.map.borrow_mut();
+        map.retain(|pos, _| {
+            extents
+                .get(pos)
+                .is_some_and(|extent| *extent <= edit.range.start)
+        });
+        let extents = map.keys().map(|pos| (*pos, extents[pos])).collect();
+        *self.extents.borrow_mut() = extents;
+        self.token_starts
+            .borrow_mut()
+            .retain(|pos, _| map.contains_key(pos));
+    }
 }
 
 /// The ["Packrat"] memoization for a parser.
@@ -56,12 +169,22 @@ pub fn memorize<L: LexIt + Clone, T: Clone>(
 ) -> Result<T, Error> {
     let pos = state.cursor();
     if let Some((value, end)) = memo.get(&pos) {
+        state.examine(memo.extent(&pos).unwrap_or(0));
         state.advance_to_cursor(end);
+        state.see_token_start(memo.token_start(&pos));
         Ok(value.clone())
     } else {
-        let value = parser(state)?;
+        let outer = state.take_extent();
+        let outer_start = state.take_token_start();
+        let result = parser(state);
+        let extent = state.take_extent();
+        let token_start = state.take_token_start();
+        state.examine(outer.max(extent));
+        state.see_token_start(outer_start.or(token_start));
+        let value = result?;
         let end = state.cursor();
-        memo.insert(pos, (value.clone(), end));
+        memo.insert_with_extent(pos, (value.clone(), end), extent);
+        memo.insert_token_start(pos, token_start);
         Ok(value)
     }
 }
@@ -106,7 +229,9 @@ pub fn left_rec<L: LexIt + Clone, T: Clone>(
 ) -> Result<T, Error> {
     let pos = state.cursor();
     if let Some((value, end)) = memo.get(&pos) {
+        state.examine(memo.extent(&pos).unwrap_or(0));
         state.advance_to_cursor(end);
+        state.see_token_start(memo.token_start(&pos));
         if let Some(value) = value {
             Ok(value.clone())
         } else {
@@ -114,7 +239,10 @@ pub fn left_rec<L: LexIt + Clone, T: Clone>(
         }
     } else {
         memo.insert(pos, (None, pos));
+        let outer = state.take_extent();
+        let outer_start = state.take_token_start();
         let mut last = (None, pos);
+        let mut token_start = None;
         loop {
             let mut fork = state.fork();
             let Ok(value) = parser(&mut fork) else { break };
@@ -123,9 +251,15 @@ pub fn left_rec<L: LexIt + Clone, T: Clone>(
                 break;
             }
             last = (Some(value), end);
+            token_start = fork.take_token_start();
             memo.insert(pos, last.clone());
+            memo.insert_token_start(pos, token_start);
         }
+        let extent = state.take_extent();
+        state.examine(outer.max(extent));
+        memo.insert_with_extent(pos, last.clone(), extent);
         state.advance_to_cursor(last.1);
+        state.see_token_start(outer_start.or(token_start));
         last.0.ok_or_else(|| state.error())
     }
 }
@@ -9,24 +9,66 @@
 //!
 //! [`ParseIt::parse`]: crate::ParseIt::parse
 
-use std::{cell::RefCell, fmt::Debug, rc::Rc};
+use std::{
+    cell::{Cell, RefCell},
+    fmt::Debug,
+    ops::Range,
+    rc::Rc,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+use regex_automata::dfa::Automaton;
 
 use crate::{
-    lexer::{Cursor, LexerState, Span, TryConvert},
+    cst::GreenElement,
+    lexer::{
+        eq_ignore_case, fold_eq, Cursor, Edit, IterLexer, LexError, LexErrorKind, LexerState,
+        Regex, SliceLexer, Span, Trivia, TryConvert,
+    },
     LexIt,
 };
 
 /// An error that occurred during parsing.
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Error {
     /// The span in the source code where the error occurred.
     pub span: Span,
+    /// The kind of the error.
+    pub kind: ErrorKind,
+}
+
+/// The kind of an [`Error`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorKind {
+    /// The tokens do not match the grammar.
+    Syntax,
+    /// The lexer failed to produce a token.
+    Lexical(LexErrorKind),
+    /// Reading the input failed.
+    Io(std::io::ErrorKind),
 }
 
 impl Error {
-    /// Create a new error from the given span.
+    /// Create a new syntax error from the given span.
     pub fn new(span: Span) -> Self {
-        Self { span }
+        Self {
+            span,
+            kind: ErrorKind::Syntax,
+        }
+    }
+
+    /// Whether this is a lexical error.
+    pub fn is_lexical(&self) -> bool {
+        matches!(self.kind, ErrorKind::Lexical(_))
+    }
+}
+
+impl From<LexError> for Error {
+    fn from(error: LexError) -> Self {
+        Self {
+            span: error.span,
+            kind: ErrorKind::Lexical(error.kind),
+        }
     }
 }
 
@@ -97,26 +139,230 @@ pub struct ParserState<'a, L> {
     lexer: L,
     lexbuf: LexerState<'a>,
     stack: Rc<RefCell<Vec<(&'static str, usize)>>>,
+    lex_error: Rc<RefCell<Option<LexError>>>,
+    syntax_error: Rc<Cell<usize>>,
+    extent: Rc<Cell<usize>>,
+    generation: u64,
+    edit: Option<(u64, Edit)>,
+    cst: Option<Rc<RefCell<Vec<GreenElement>>>>,
+    cst_len: usize,
+    token_start: Option<usize>,
 }
 
 impl<'a, L: LexIt + Clone> ParserState<'a, L> {
     /// Create a new parser state from the given lexer.
-    pub fn new(input: &'a str) -> Self {
+    pub fn new(input: &'a str) -> Self
+    where
+        L: Default,
+    {
+        Self::with_lexer(input, L::new())
+    }
+
+    /// Create a new parser state with the given lexer instance, e.g. a lexer
+    /// with inputs.
+    pub fn with_lexer(input: &'a str, lexer: L) -> Self {
+        Self::with_lexbuf(LexerState::new(input), lexer)
+    }
+
+    /// Create a new parser state over raw bytes.
+    ///
+    /// See [`LexerState::from_bytes`].
+    ///
+    /// ```
+    /// # use parse_it::*;
+    /// let mut state = ParserState::<ByteLexer>::from_bytes(b"GIF\xff");
+    /// assert_eq!(state.parse_bytes(b"GIF").unwrap(), b"GIF");
+    /// assert_eq!(state.parse_byte(0xff).unwrap(), 0xff);
+    /// assert!(state.is_empty());
+    /// ```
+    pub fn from_bytes(input: &'a [u8]) -> Self
+    where
+        L: Default,
+    {
+        Self::with_lexbuf(LexerState::from_bytes(input), L::new())
+    }
+
+    fn with_lexbuf(lexbuf: LexerState<'a>, lexer: L) -> Self {
+        // tells the parses of different texts apart, as memos are reused
+        // only by edits of the text they were filled for
+        static GENERATION: AtomicU64 = AtomicU64::new(1);
         Self {
-            lexer: L::new(),
-            lexbuf: LexerState::new(input),
+            lexer,
+            lexbuf,
             stack: Rc::new(RefCell::new(Vec::new())),
+            lex_error: Rc::new(RefCell::new(None)),
+            syntax_error: Default::default(),
+            extent: Default::default(),
+            generation: GENERATION.fetch_add(1, Ordering::Relaxed),
+            edit: None,
+            cst: None,
+            cst_len: 0,
+            token_start: None,
+        }
+    }
+
+    /// Create a parser state over the edited text, to reparse it
+    /// incrementally.
+    ///
+    /// The next parse with a parser whose last parse was of this state reuses
+    /// the results of that parse that are unaffected by the edit: those that did
+    /// not examine the edited range, and those after it, which are moved to
+    /// their positions in the edited text. Regexes that cannot be compiled to
+    /// DFAs at macro expansion time are assumed to examine all the input after
+    /// them, so the results that depend on them are only reused after the
+    /// edit. Results that may hold positions, from spanned terminals,
+    /// `state!()` or generated types, are never moved, only reused before the
+    /// edit. If the parser has parsed another text since, it starts over.
+    ///
+    /// ```
+    /// # use parse_it::{*, lexer::Edit};
+    /// parse_it::parse_it! {
+    ///     #[parser]
+    ///     mod parse {
+    ///         type Lexer = parse_it::CharLexer;
+    ///
+    ///         pub Digits -> String {
+    ///             digits:Digit* => digits.into_iter().collect(),
+    ///         }
+    ///
+    ///         Digit -> char {
+    ///             c:<char> => c,
+    ///         }
+    ///     }
+    /// }
+    ///
+    /// let parser = parse::Digits::default();
+    /// let mut state = ParserState::new("1234");
+    /// assert_eq!(parser.parse_stream(&mut state).unwrap(), "1234");
+    ///
+    /// let mut state = state.edit("15634", &Edit::new(1..2, "56"));
+    /// assert_eq!(parser.parse_stream(&mut state).unwrap(), "15634");
+    /// ```
+    pub fn edit<'b>(&self, input: &'b str, edit: &Edit) -> ParserState<'b, L> {
+        let (lexbuf, edit) = self.lexbuf.edit(input, edit);
+        ParserState {
+            edit: Some((self.generation, edit)),
+            ..ParserState::with_lexbuf(lexbuf, self.lexer.clone())
+        }
+    }
+
+    /// Detach the state from its input, keeping what an edit appending to the
+    /// input reuses, so that the input can be extended in place.
+    ///
+    /// Returns the edit to complete with the length of the appended input,
+    /// along with the generation of this state.
+    pub(crate) fn detach(&self) -> (LexerState<'static>, (u64, Edit)) {
+        let len = self.lexbuf.source_bytes().len();
+        let edit = Edit {
+            range: len..len,
+            len: 0,
+        };
+        let (lexbuf, edit) = self.lexbuf.edit_bytes(&[], &edit);
+        (lexbuf, (self.generation, edit))
+    }
+
+    /// Create a parser state over the extended input of a detached state, to
+    /// reparse it incrementally.
+    pub(crate) fn reattach(lexbuf: LexerState<'static>, input: &'a [u8], edit: (u64, Edit)) -> Self
+    where
+        L: Default,
+    {
+        ParserState {
+            edit: Some(edit),
+            ..ParserState::with_lexbuf(lexbuf.with_input(input), L::new())
         }
     }
 
+    /// Take the edit this state was created with, along with the generation
+    /// of the edited state, to update the memoized results of a parser.
+    #[doc(hidden)]
+    pub fn take_edit(&mut self) -> Option<(u64, Edit)> {
+        self.edit.take()
+    }
+
+    /// The generation of this state, which tells the parses of different
+    /// texts apart. Forks of a state share its generation.
+    #[doc(hidden)]
+    pub fn generation(&self) -> u64 {
+        self.generation
+    }
+
+    /// Record that the input has been examined up to the given position,
+    /// exclusive.
+    pub(crate) fn examine(&self, extent: usize) {
+        self.extent.set(self.extent.get().max(extent));
+    }
+
+    /// Record the input examined by the lexer, which is at least one byte past
+    /// the last token.
+    fn examine_lexer(&mut self) {
+        let examined = self.lexbuf.take_examined();
+        self.examine(examined.max(self.lexbuf.span().end + 1));
+    }
+
+    /// Take the extent of the input examined so far, resetting it.
+    pub(crate) fn take_extent(&self) -> usize {
+        self.extent.take()
+    }
+
+    /// Get the extent of the input examined so far.
+    pub(crate) fn extent(&self) -> usize {
+        self.extent.get()
+    }
+
+    /// Get all the source bytes.
+    pub(crate) fn source_bytes(&self) -> &'a [u8] {
+        self.lexbuf.source_bytes()
+    }
+
+    /// Get the position in the input after the last parsed token.
+    pub(crate) fn position(&self) -> usize {
+        self.lexbuf.span().end
+    }
+
     /// Get the current parsing position.
     pub fn cursor(&self) -> Cursor {
         self.lexbuf.cursor()
     }
 
     /// Advance to the next token.
-    fn next(&mut self) -> Option<L::Token<'a>> {
-        self.lexer.next(&mut self.lexbuf)
+    fn next(&mut self) -> Result<L::Token<'a>, Error> {
+        let end = self.lexeme_end();
+        let result = self.lexer.next(&mut self.lexbuf);
+        self.examine_lexer();
+        match result {
+            Ok(Some(token)) => {
+                self.record_token(end);
+                Ok(token)
+            }
+            Ok(None) => Err(self.error()),
+            Err(error) => Err(self.lex_error(error)),
+        }
+    }
+
+    /// Advance to the next token, along with its span.
+    fn next_spanned(&mut self) -> Result<(L::Token<'a>, Span), Error> {
+        let end = self.lexeme_end();
+        let result = self.lexer.next_spanned(&mut self.lexbuf);
+        self.examine_lexer();
+        match result {
+            Ok(Some(token)) => {
+                self.record_token(end);
+                Ok(token)
+            }
+            Ok(None) => Err(self.error()),
+            Err(error) => Err(self.lex_error(error)),
+        }
+    }
+
+    /// Record a lexical error, keeping the furthest one.
+    fn lex_error(&self, error: LexError) -> Error {
+        self.examine(error.span.end + 1);
+        let mut lex_error = self.lex_error.borrow_mut();
+        if lex_error.is_none_or(|e| e.span.start < error.span.start) {
+            *lex_error = Some(error);
+        }
+        error.into()
     }
 
     /// Consume the next token if it matches the given token.
@@ -124,7 +370,15 @@ impl<'a, L: LexIt + Clone> ParserState<'a, L> {
         &mut self,
         matches: impl FnOnce(L::Token<'a>) -> Option<T>,
     ) -> Result<T, Error> {
-        self.next().and_then(matches).ok_or_else(|| self.error())
+        matches(self.next()?).ok_or_else(|| self.error())
+    }
+
+    /// Consume the next token if it matches the given token, with its span.
+    pub fn parse_spanned_with<T>(
+        &mut self,
+        matches: impl FnOnce((L::Token<'a>, Span)) -> Option<T>,
+    ) -> Result<T, Error> {
+        matches(self.next_spanned()?).ok_or_else(|| self.error())
     }
 
     /// Parse a token that can be converted to the given type.
@@ -136,33 +390,237 @@ impl<'a, L: LexIt + Clone> ParserState<'a, L> {
         self.parse_with(|tt| tt.try_convert())
     }
 
+    /// Parse a token of the given kind.
+    ///
+    /// See [`LexIt::LITERALS`] for how token kinds are defined.
+    pub fn parse_kind(&mut self, kind: usize) -> Result<L::Token<'a>, Error> {
+        self.parse_with(|tt| (L::kind(&tt) == Some(kind)).then_some(tt))
+    }
+
     /// Parse a token that exactly matches the given character.
     pub fn parse_char(&mut self, c: char) -> Result<char, Error> {
-        self.next().ok_or_else(|| self.error())?;
-        let lexeme = self.lexbuf.lexeme();
+        self.next()?;
+        if self.lexbuf.lexeme_bytes() == c.encode_utf8(&mut [0; 4]).as_bytes() {
+            Ok(c)
+        } else {
+            Err(self.error())
+        }
+    }
+
+    /// Parse a token that exactly matches the given string.
+    pub fn parse_str(&mut self, literal: &'a str) -> Result<&str, Error> {
+        self.next()?;
+        if self.lexbuf.lexeme_bytes() == literal.as_bytes() {
+            Ok(literal)
+        } else {
+            Err(self.error())
+        }
+    }
+
+    /// Parse a token that exactly matches the given byte.
+    pub fn parse_byte(&mut self, b: u8) -> Result<u8, Error> {
+        self.next()?;
+        if self.lexbuf.lexeme_bytes() == [b] {
+            Ok(b)
+        } else {
+            Err(self.error())
+        }
+    }
+
+    /// Parse tokens that exactly match the given bytes.
+    ///
+    /// The literal may span several tokens, so that `b"GIF"` matches three
+    /// tokens of [`ByteLexer`], as well as a single `GIF` token of a lexer
+    /// with such a rule.
+    ///
+    /// [`ByteLexer`]: crate::ByteLexer
+    pub fn parse_bytes(&mut self, literal: &[u8]) -> Result<&'a [u8], Error> {
+        self.next()?;
+        let start = self.lexbuf.span().start;
+        loop {
+            let span = Span {
+                start,
+                end: self.lexbuf.span().end,
+            };
+            let matched = self.lexbuf.slice_bytes(span);
+            if matched == literal {
+                return Ok(matched);
+            }
+            if !literal.starts_with(matched) {
+                return Err(self.error());
+            }
+            self.next()?;
+        }
+    }
+
+    /// Get the current lexeme as a string, or `None` if it is not valid UTF-8.
+    fn lexeme_str(&self) -> Option<&'a str> {
+        std::str::from_utf8(self.lexbuf.lexeme_bytes()).ok()
+    }
+
+    /// Parse a token that matches the given character, ignoring case.
+    ///
+    /// Characters are compared under Unicode simple case folding.
+    pub fn parse_char_ci(&mut self, c: char) -> Result<char, Error> {
+        self.next()?;
+        let lexeme = self.lexeme_str().ok_or_else(|| self.error())?;
         let mut chars = lexeme.chars();
         let ch = chars.next().ok_or_else(|| self.error())?;
-        if ch == c && chars.as_str().is_empty() {
+        if fold_eq(ch, c) && chars.as_str().is_empty() {
             Ok(ch)
         } else {
             Err(self.error())
         }
     }
 
-    /// Parse a token that exactly matches the given string.
-    pub fn parse_str(&mut self, literal: &'a str) -> Result<&str, Error> {
-        self.next().ok_or_else(|| self.error())?;
-        let lexeme = self.lexbuf.lexeme();
-        if lexeme == literal {
+    /// Parse a token that matches the given string, ignoring case.
+    ///
+    /// Strings are compared character by character under Unicode simple case
+    /// folding. The lexeme as it appears in the source is returned.
+    ///
+    /// ```
+    /// # use parse_it::*;
+    /// let mut state = ParserState::<CharLexer>::new("ſ");
+    /// assert_eq!(state.parse_str_ci("S").unwrap(), "ſ");
+    /// ```
+    pub fn parse_str_ci(&mut self, literal: &str) -> Result<&'a str, Error> {
+        self.next()?;
+        let lexeme = self.lexeme_str().ok_or_else(|| self.error())?;
+        if eq_ignore_case(lexeme, literal) {
             Ok(lexeme)
         } else {
             Err(self.error())
         }
     }
 
+    /// Parse the input matched by the given regex at the current position.
+    ///
+    /// The regex runs directly against the source text, bypassing the lexer,
+    /// which is convenient for scannerless grammars over [`CharLexer`].
+    ///
+    /// ```
+    /// # use parse_it::{*, lexer::Regex};
+    /// let regex = Regex::new(r"[0-9]+").unwrap();
+    /// let mut state = ParserState::<CharLexer>::new("123+4");
+    /// assert_eq!(state.parse_regex(&regex).unwrap(), "123");
+    /// assert_eq!(state.parse_char('+').unwrap(), '+');
+    /// assert_eq!(state.parse_regex(&regex).unwrap(), "4");
+    /// ```
+    ///
+    /// [`CharLexer`]: crate::CharLexer
+    pub fn parse_regex(&mut self, regex: &Regex) -> Result<&'a str, Error> {
+        let end = self.lexeme_end();
+        let pattern = self.lexbuf.run(regex);
+        self.examine_lexer();
+        pattern.ok_or_else(|| self.error())?;
+        self.record_token(end);
+        self.lexbuf.lexeme().map_err(|error| self.lex_error(error))
+    }
+
+    /// Parse the input matched by the given DFA at the current position.
+    ///
+    /// This is what regex terminals in parser productions compile to, unless
+    /// they are not supported by DFAs. See [`parse_regex`](Self::parse_regex).
+    ///
+    /// The DFA must support anchored searches.
+    pub fn parse_dfa<A: Automaton>(&mut self, dfa: &A) -> Result<&'a str, Error> {
+        let end = self.lexeme_end();
+        let pattern = self.lexbuf.run_dfa(dfa);
+        self.examine_lexer();
+        pattern.ok_or_else(|| self.error())?;
+        self.record_token(end);
+        self.lexbuf.lexeme().map_err(|error| self.lex_error(error))
+    }
+
+    /// Get the leading trivia of the token at the given span.
+    ///
+    /// See [`LexerState::leading_trivia`].
+    pub fn leading_trivia(&self, span: Span) -> Vec<Trivia<'a>> {
+        self.lexbuf.leading_trivia(span)
+    }
+
+    /// Get the trailing trivia of the token at the given span.
+    ///
+    /// See [`LexerState::trailing_trivia`].
+    pub fn trailing_trivia(&self, span: Span) -> Vec<Trivia<'a>> {
+        self.lexbuf.trailing_trivia(span)
+    }
+
     /// Report an error at the current position.
     pub fn error(&self) -> Error {
-        Error::new(self.lexbuf.span())
+        let span = self.lexbuf.span();
+        self.syntax_error
+            .set(self.syntax_error.get().max(span.start));
+        Error::new(span)
+    }
+
+    /// Surface the lexical error that caused the parsing to fail, if any.
+    ///
+    /// Syntax errors from backtracking hide the lexical errors found along the
+    /// way, so the top-level parser reports the furthest lexical error instead
+    /// of the given one, if no syntax error was found past it. Lexical errors
+    /// that another alternative got past, e.g. with a regex terminal, are not
+    /// the cause of the failure.
+    ///
+    /// ```
+    /// # use parse_it::ParseIt;
+    /// parse_it::parse_it! {
+    ///     #[lexer]
+    ///     mod lex {
+    ///         pub Initial -> &'lex str {
+    ///             r"\s+" => continue,
+    ///             r"[a-z]+" => self,
+    ///         }
+    ///     }
+    ///
+    ///     #[parser]
+    ///     mod parse {
+    ///         type Lexer = super::lex::Initial;
+    ///
+    ///         pub Line -> () {
+    ///             <&str> => (),
+    ///             "[0-9]+"re <&str> <&str> => (),
+    ///         }
+    ///     }
+    /// }
+    ///
+    /// # fn main() {
+    /// let parser = parse::Line::default();
+    /// // `1` cannot be lexed, but the second production gets past it
+    /// let error = parser.parse("12 ab").unwrap_err();
+    /// assert!(!error.is_lexical());
+    /// let error = parser.parse("%").unwrap_err();
+    /// assert!(error.is_lexical());
+    /// # }
+    /// ```
+    pub fn surface_error(&self, error: Error) -> Error {
+        match *self.lex_error.borrow() {
+            Some(lex_error)
+                if lex_error.span.start >= self.syntax_error.get().max(error.span.start) =>
+            {
+                lex_error.into()
+            }
+            _ => error,
+        }
+    }
+
+    /// Forget the errors found so far, before parsing an unrelated item.
+    pub(crate) fn clear_errors(&self) {
+        self.lex_error.borrow_mut().take();
+        self.syntax_error.take();
+    }
+
+    /// Skip the next token, or the next character if it cannot be lexed, to
+    /// recover from an error. Returns `false` if nothing could be skipped.
+    pub(crate) fn skip(&mut self) -> bool {
+        let cursor = self.cursor();
+        if self.next().is_err() && self.cursor() == cursor {
+            self.lexbuf
+                .advance_char()
+                .map(|_| ())
+                .or_else(|| self.lexbuf.advance_byte().map(|_| ()));
+        }
+        self.cursor() != cursor
     }
 
     /// Whether the parser is at the end of the input.
@@ -170,12 +628,20 @@ impl<'a, L: LexIt + Clone> ParserState<'a, L> {
         self.lexbuf.is_empty()
     }
 
+    /// Whether the lexer has no token left, skipping the trivia before the end
+    /// of the input.
+    pub(crate) fn at_end(&self) -> bool {
+        self.is_empty() || matches!(self.lexer.next(&mut self.lexbuf.clone()), Ok(None))
+    }
+
     /// Advance the state to the given state.
     ///
     /// # Panics
     /// Panics if the given state is before the current state.
     pub fn advance_to(&mut self, other: &Self) {
-        self.advance_to_cursor(other.lexbuf.cursor())
+        self.advance_to_cursor(other.lexbuf.cursor());
+        self.cst_len = other.cst_len;
+        self.token_start = other.token_start;
     }
 
     /// Advance the state to the given position.
@@ -193,6 +659,103 @@ impl<'a, L: LexIt + Clone> ParserState<'a, L> {
             lexer: self.lexer.clone(),
             lexbuf: self.lexbuf.clone(),
             stack: self.stack.clone(),
+            lex_error: self.lex_error.clone(),
+            syntax_error: self.syntax_error.clone(),
+            extent: self.extent.clone(),
+            generation: self.generation,
+            edit: None,
+            cst: self.cst.clone(),
+            cst_len: self.cst_len,
+            token_start: self.token_start,
+        }
+    }
+
+    /// Record a concrete syntax tree while parsing, or stop recording it.
+    ///
+    /// Only the rules of parser modules marked with `#[parse_it(cst)]` record
+    /// their nodes. See [`cst`](crate::cst).
+    pub fn record_cst(&mut self, record: bool) {
+        self.cst = record.then(Default::default);
+        self.cst_len = 0;
+    }
+
+    #[doc(hidden)]
+    pub fn records_cst(&self) -> bool {
+        self.cst.is_some()
+    }
+
+    /// Record the token just consumed as a child of the current node, along
+    /// with the trivia between it and the end of the previous token.
+    fn record_token(&mut self, end: usize) {
+        let span = self.lexbuf.span();
+        self.see_token_start(Some(span.start));
+        if end < span.start {
+            let trivia = Span {
+                start: end,
+                end: span.start,
+            };
+            self.cst_push(self.cst_len, GreenElement::Trivia(trivia));
+        }
+        self.cst_push(self.cst_len, GreenElement::Token(span));
+    }
+
+    /// The position in the recorded children where the current node starts.
+    #[doc(hidden)]
+    pub fn cst_mark(&self) -> usize {
+        self.cst_len
+    }
+
+    /// Take the children recorded since the given mark.
+    #[doc(hidden)]
+    pub fn cst_take(&mut self, mark: usize) -> Vec<GreenElement> {
+        let Some(cst) = &self.cst else {
+            return vec![];
+        };
+        let mut cst = cst.borrow_mut();
+        // the children past this state were recorded by abandoned forks
+        cst.truncate(self.cst_len);
+        self.cst_len = mark;
+        cst.drain(mark..).collect()
+    }
+
+    /// Replace the children recorded since the given mark with the given one.
+    #[doc(hidden)]
+    pub fn cst_push(&mut self, mark: usize, element: GreenElement) {
+        if let Some(cst) = &self.cst {
+            let mut cst = cst.borrow_mut();
+            cst.truncate(mark);
+            cst.push(element);
+            self.cst_len = mark + 1;
+        }
+    }
+
+    /// The end of the last consumed lexeme.
+    #[doc(hidden)]
+    pub fn lexeme_end(&self) -> usize {
+        self.lexbuf.span().end
+    }
+
+    /// Take the start of the first token consumed so far by the current rule,
+    /// to track that of a nested rule.
+    pub(crate) fn take_token_start(&mut self) -> Option<usize> {
+        self.token_start.take()
+    }
+
+    /// Record the start of a consumed token, unless the current rule has
+    /// consumed one already.
+    pub(crate) fn see_token_start(&mut self, start: Option<usize>) {
+        self.token_start = self.token_start.or(start);
+    }
+
+    /// The span from the start of the first token consumed by the current
+    /// rule to the end of the last one, or an empty span after the last token
+    /// if the rule consumed none.
+    #[doc(hidden)]
+    pub fn rule_span(&self) -> Span {
+        let end = self.lexeme_end();
+        Span {
+            start: self.token_start.unwrap_or(end),
+            end,
         }
     }
 
@@ -211,3 +774,56 @@ impl<'a, L: LexIt + Clone> ParserState<'a, L> {
         format!("{:?}", self.stack.borrow())
     }
 }
+
+impl<'a, L: LexIt + Clone + Default> From<&'a str> for ParserState<'a, L> {
+    fn from(input: &'a str) -> Self {
+        Self::new(input)
+    }
+}
+
+impl<'a, T: Clone> ParserState<'a, SliceLexer<T>> {
+    /// Create a new parser state over pre-lexed tokens and their spans.
+    ///
+    /// ```
+    /// # use parse_it::{*, lexer::{SliceLexer, Span}};
+    /// let tokens = [
+    ///     ("let", Span { start: 0, end: 3 }),
+    ///     ("x", Span { start: 4, end: 5 }),
+    /// ];
+    /// let mut state = ParserState::<SliceLexer<&str>>::from_tokens(&tokens);
+    /// assert_eq!(state.parse_with(|tt| (tt == "let").then_some(tt)).unwrap(), "let");
+    /// let error = state.parse_with(|tt| (tt == "=").then_some(tt)).unwrap_err();
+    /// assert_eq!(error.span, Span { start: 4, end: 5 });
+    /// ```
+    pub fn from_tokens(tokens: &[(T, Span)]) -> Self {
+        let lexer = SliceLexer::from_tokens(tokens);
+        Self::with_lexbuf(lexer.lexer_state(), lexer)
+    }
+}
+
+impl<'a, T: Clone + 'a> ParserState<'a, IterLexer<T>> {
+    /// Create a new parser state over the tokens of an external lexer, and
+    /// their byte ranges.
+    ///
+    /// ```
+    /// # use parse_it::{*, lexer::IterLexer};
+    /// let words = "let x".split(' ').scan(0, |start, word| {
+    ///     let range = *start..*start + word.len();
+    ///     *start = range.end + 1;
+    ///     Some((word, range))
+    /// });
+    /// let mut state = ParserState::<IterLexer<_>>::from_token_iter(words);
+    /// assert_eq!(state.parse_with(|tt| (tt == "let").then_some(tt)).unwrap(), "let");
+    /// assert!(!state.is_empty());
+    /// assert_eq!(state.parse_with(Some).unwrap(), "x");
+    /// assert!(state.is_empty());
+    /// ```
+    pub fn from_token_iter<I>(tokens: I) -> Self
+    where
+        I: IntoIterator<Item = (T, Range<usize>)>,
+        I::IntoIter: 'a,
+    {
+        let (lexer, lexbuf) = IterLexer::from_tokens(tokens);
+        Self::with_lexbuf(lexbuf, lexer)
+    }
+}
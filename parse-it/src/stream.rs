@@ -0,0 +1,373 @@
+//! Parsing from streaming input, and parsing sequences lazily.
+
+use std::io::BufRead;
+
+use crate::{
+    lexer::{Edit, LexerState, Span},
+    Error, ErrorKind, LexIt, ParseIt, ParserState,
+};
+
+/// An iterator parsing items one at a time from a reader.
+///
+/// This is created by [`ParseIt::parse_reader`]. The parser parses a single
+/// item, and is applied repeatedly until the input ends, so that only the item
+/// being parsed is held in memory. Each item is parsed over raw bytes, as with
+/// [`ParserState::from_bytes`], and errors are reported at their positions in
+/// the whole input.
+///
+/// The buffered input is a window of the input, from the start of the item
+/// being parsed: the oldest position a fork of the parser can backtrack to, as
+/// the tokens borrow from the window. Chunks are read from the reader into the
+/// window until the parser no longer examines the end of it, which is then the
+/// end of the item. Each time the window is extended, the item is parsed again
+/// incrementally, as with [`ParserState::edit`], so the results that did not
+/// examine the end of the window are reused. Once the item is parsed, nothing
+/// before its end can be backtracked to, so the consumed input is discarded.
+///
+/// The iteration stops after the first error, if an item consumes no input,
+/// or if only trivia are left.
+///
+/// ```
+/// use parse_it::ParseIt;
+///
+/// parse_it::parse_it! {
+///     #[parser]
+///     mod parse {
+///         type Lexer = parse_it::CharLexer;
+///
+///         pub Line -> String {
+///             line:r"[^\n]*"re '\n' => line.to_string(),
+///         }
+///     }
+/// }
+///
+/// let input = std::io::BufReader::with_capacity(4, "one\ntwo\nthree\n".as_bytes());
+/// let parser = parse::Line::default();
+/// let lines = parser.parse_reader(input).collect::<Result<Vec<_>, _>>().unwrap();
+/// assert_eq!(lines, ["one", "two", "three"]);
+/// ```
+///
+/// The items end where the lexer has no token left, so trivia after the last
+/// item is skipped:
+///
+/// ```
+/// use parse_it::ParseIt;
+///
+/// parse_it::parse_it! {
+///     #[lexer]
+///     mod lex {
+///         pub Initial -> &'lex str {
+///             r"\s+" => #[trivia],
+///             r"[0-9]+" => self,
+///             ";" => self,
+///         }
+///     }
+///
+///     #[parser]
+///     mod parse {
+///         type Lexer = super::lex::Initial;
+///
+///         pub Number -> u32 {
+///             n:<&str> ";" => n.parse().unwrap(),
+///         }
+///     }
+/// }
+///
+/// fn main() {
+///     let input = std::io::BufReader::with_capacity(4, "1; 22;\n333;\n".as_bytes());
+///     let parser = parse::Number::default();
+///     let numbers = parser.parse_reader(input).collect::<Result<Vec<_>, _>>();
+///     assert_eq!(numbers.unwrap(), [1, 22, 333]);
+/// }
+/// ```
+pub struct ReadItems<'p, P, R> {
+    parser: &'p P,
+    reader: R,
+    buffer: Vec<u8>,
+    offset: usize,
+    /// The lexer state of the item being parsed, detached from the buffer to
+    /// read more input, and the edit appending it, with the generation of the
+    /// state it edits.
+    detached: Option<(LexerState<'static>, (u64, Edit))>,
+    eof: bool,
+    done: bool,
+}
+
+impl<'p, P: ParseIt, R: BufRead> ReadItems<'p, P, R> {
+    /// Create an iterator parsing items from the given reader.
+    pub fn new(parser: &'p P, reader: R) -> Self {
+        Self {
+            parser,
+            reader,
+            buffer: vec![],
+            offset: 0,
+            detached: None,
+            eof: false,
+            done: false,
+        }
+    }
+
+    /// Read chunks until the buffered input doubles, or the input ends,
+    /// returning the length read.
+    fn fill(&mut self) -> std::io::Result<usize> {
+        let start = self.buffer.len();
+        loop {
+            let chunk = self.reader.fill_buf()?;
+            if chunk.is_empty() {
+                self.eof = true;
+                return Ok(self.buffer.len() - start);
+            }
+            let len = chunk.len();
+            self.buffer.extend_from_slice(chunk);
+            self.reader.consume(len);
+            if self.buffer.len() >= start * 2 {
+                return Ok(self.buffer.len() - start);
+            }
+        }
+    }
+
+    /// Read more input, as with [`fill`](Self::fill), reporting an error at
+    /// the end of the buffered input if reading fails.
+    fn read_more(&mut self) -> Result<usize, Error> {
+        self.fill().map_err(|e| {
+            let end = self.buffer.len();
+            self.error(Span { start: end, end }, ErrorKind::Io(e.kind()))
+        })
+    }
+
+    /// Report an error at the given span of the buffered input.
+    fn error(&mut self, span: Span, kind: ErrorKind) -> Error {
+        self.done = true;
+        Error {
+            span: Span {
+                start: self.offset + span.start,
+                end: self.offset + span.end,
+            },
+            kind,
+        }
+    }
+}
+
+impl<P: ParseIt, R: BufRead> Iterator for ReadItems<'_, P, R>
+where
+    P::Lexer: Default,
+{
+    type Item = Result<P::Output, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        loop {
+            let mut state = match self.detached.take() {
+                Some((lexbuf, edit)) => ParserState::reattach(lexbuf, &self.buffer, edit),
+                None => {
+                    let state = ParserState::<P::Lexer>::from_bytes(&self.buffer);
+                    // the trivia after the last item is not an item
+                    if state.at_end() {
+                        drop(state);
+                        if self.eof {
+                            return None;
+                        }
+                        if let Err(e) = self.read_more() {
+                            return Some(Err(e));
+                        }
+                        continue;
+                    }
+                    state
+                }
+            };
+            let result = self.parser.parse_stream(&mut state);
+            let consumed = state.position();
+            // the item may go on in the input yet to be read
+            let detached =
+                (!self.eof && state.extent() > self.buffer.len()).then(|| state.detach());
+            // the state borrows the buffer until it is dropped
+            drop(state);
+            if let Some((lexbuf, (generation, mut edit))) = detached {
+                match self.read_more() {
+                    Ok(len) => {
+                        edit.len = len;
+                        self.detached = Some((lexbuf, (generation, edit)));
+                    }
+                    Err(e) => return Some(Err(e)),
+                }
+                continue;
+            }
+            return Some(match result {
+                Ok(_) if consumed == 0 => {
+                    let span = Span { start: 0, end: 0 };
+                    Err(self.error(span, ErrorKind::Syntax))
+                }
+                Ok(value) => {
+                    self.buffer.drain(..consumed);
+                    self.offset += consumed;
+                    Ok(value)
+                }
+                Err(e) => Err(self.error(e.span, e.kind)),
+            });
+        }
+    }
+}
+
+/// An iterator lazily parsing a sequence of items, optionally separated.
+///
+/// This is what the `parse_iter` method generated for a parser marked with
+/// `#[parse_it(iter(Item, sep = ...))]` returns: each call to `next` parses a
+/// single item, so that the items can be processed before the whole sequence
+/// is parsed. The sequence may end with a separator.
+///
+/// After an error, parsing recovers by skipping the input up to the next
+/// separator, and goes on with the item after it. Without a separator, the
+/// iteration stops after the first error.
+///
+/// ```
+/// use parse_it::{stream::Items, CharLexer, ParserState};
+///
+/// let state = ParserState::<CharLexer>::new("1,x,3");
+/// let items = Items::new(
+///     state,
+///     |state| state.parse_type::<char>()?.to_digit(10).ok_or(state.error()),
+///     Some(|state: &mut ParserState<_>| state.parse_char(',').map(|_| ())),
+/// );
+/// let items = items.map(|item| item.ok()).collect::<Vec<_>>();
+/// assert_eq!(items, [Some(1), None, Some(3)]);
+/// ```
+///
+/// The sequence ends where the lexer has no token left, so trivia after the
+/// last item is skipped:
+///
+/// ```
+/// use parse_it::ParseIt;
+///
+/// parse_it::parse_it! {
+///     #[lexer]
+///     mod lex {
+///         pub Initial -> &'lex str {
+///             r"\s+" => #[trivia],
+///             r"\w+" => self,
+///             "," => self,
+///         }
+///     }
+///
+///     #[parser]
+///     mod parse {
+///         type Lexer = super::lex::Initial;
+///
+///         #[parse_it(iter(Word, sep = ","))]
+///         pub Words -> Vec<String> {
+///             Word* => self,
+///         }
+///
+///         Word -> String {
+///             word:<&str> => word.to_string(),
+///         }
+///     }
+/// }
+///
+/// fn main() {
+///     let parser = parse::Words::default();
+///     let words = parser.parse_iter("one, two,\n").collect::<Result<Vec<_>, _>>();
+///     assert_eq!(words.unwrap(), ["one", "two"]);
+///     let words = parser.parse_iter("one, two \n").collect::<Result<Vec<_>, _>>();
+///     assert_eq!(words.unwrap(), ["one", "two"]);
+/// }
+/// ```
+pub struct Items<'a, L, I, S> {
+    state: ParserState<'a, L>,
+    item: I,
+    sep: Option<S>,
+    expect_sep: bool,
+    done: bool,
+}
+
+impl<'a, L, T, I, S> Items<'a, L, I, S>
+where
+    L: LexIt + Clone,
+    I: FnMut(&mut ParserState<'a, L>) -> Result<T, Error>,
+    S: FnMut(&mut ParserState<'a, L>) -> Result<(), Error>,
+{
+    /// Create an iterator applying the item parser repeatedly from the given
+    /// state, with the separator parser between the items.
+    pub fn new(state: ParserState<'a, L>, item: I, sep: Option<S>) -> Self {
+        Self {
+            state,
+            item,
+            sep,
+            expect_sep: false,
+            done: false,
+        }
+    }
+
+    /// Parse the next separator, if one is expected.
+    fn parse_sep(&mut self) -> Result<(), Error> {
+        match &mut self.sep {
+            Some(sep) if self.expect_sep => {
+                let fork = &mut self.state.fork();
+                sep(fork)?;
+                self.state.advance_to(fork);
+                self.expect_sep = false;
+                Ok(())
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Parse the next item, requiring it to consume some input.
+    fn parse_item(&mut self) -> Result<T, Error> {
+        self.state.clear_errors();
+        let fork = &mut self.state.fork();
+        let value = (self.item)(fork).map_err(|e| fork.surface_error(e))?;
+        if fork.cursor() == self.state.cursor() {
+            return Err(self.state.error());
+        }
+        self.state.advance_to(fork);
+        self.expect_sep = self.sep.is_some();
+        Ok(value)
+    }
+
+    /// Skip the input up to and including the next separator.
+    fn recover(&mut self) {
+        let Some(sep) = &mut self.sep else {
+            self.done = true;
+            return;
+        };
+        loop {
+            let fork = &mut self.state.fork();
+            if sep(fork).is_ok() && fork.cursor() != self.state.cursor() {
+                self.state.advance_to(fork);
+                self.expect_sep = false;
+                return;
+            }
+            if !self.state.skip() {
+                self.done = true;
+                return;
+            }
+        }
+    }
+}
+
+impl<'a, L, T, I, S> Iterator for Items<'a, L, I, S>
+where
+    L: LexIt + Clone,
+    I: FnMut(&mut ParserState<'a, L>) -> Result<T, Error>,
+    S: FnMut(&mut ParserState<'a, L>) -> Result<(), Error>,
+{
+    type Item = Result<T, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done || self.state.at_end() {
+            return None;
+        }
+        let result = match self.parse_sep() {
+            // a trailing separator
+            Ok(()) if self.state.at_end() => return None,
+            Ok(()) => self.parse_item(),
+            Err(e) => Err(e),
+        };
+        if result.is_err() {
+            self.recover();
+        }
+        Some(result)
+    }
+}